@@ -0,0 +1,37 @@
+use std::fs;
+use std::time::Instant;
+
+use minigrep::Config;
+
+//a tree like a mid-sized repository: 40 directories of 50 files of 1,000 lines each
+const DIRS: usize = 40;
+const FILES: usize = 50;
+const LINES: usize = 1_000;
+
+fn main() {
+    let root = std::env::temp_dir().join(format!("minigrep-bench-{}", std::process::id()));
+    let text: String = (0..LINES).map(|i| format!("line {} of some ordinary source text, fn main() {{}}\n", i)).collect();
+    for dir in 0..DIRS {
+        let dir = root.join(format!("dir{}", dir));
+        fs::create_dir_all(&dir).unwrap();
+        for file in 0..FILES {
+            fs::write(dir.join(format!("file{}.txt", file)), &text).unwrap();
+        }
+    }
+
+    let cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get());
+    println!("{} files, {} lines, {} CPUs", DIRS * FILES, DIRS * FILES * LINES, cpus);
+    //a regex nothing matches: every line is read and tested, and nothing is printed
+    for &jobs in &[1, 2, 4, 8] {
+        let args = vec!["minigrep", "-rE", "-j", &jobs.to_string(), "needle[0-9]+x", root.to_str().unwrap()]
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        let start = Instant::now();
+        let matched = minigrep::run(Config::new(args.into_iter()).unwrap()).unwrap();
+        assert!(!matched);
+        println!("-j{:<3} {:>8.1?}", jobs, start.elapsed());
+    }
+
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,398 @@
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+use std::thread;
+
+use crate::Config;
+
+pub const USAGE: &str = "\
+Usage: minigrep [OPTIONS] QUERY [FILE...]
+
+With no FILE, or when FILE is -, standard input is searched (the current directory with -r).
+Exit status is 0 if a line was selected, 1 if none was and 2 if there was an error.
+
+Options:
+  -E, --regex               treat QUERY as a regular expression
+  -i, --ignore-case         match case-insensitively (the default when CASE_INSENSITIVE is set)
+  -s, --case-sensitive      match case-sensitively, even when CASE_INSENSITIVE is set
+  -S, --smart-case          match case-insensitively, unless QUERY has an uppercase letter
+  -v, --invert-match        print the lines that don't match
+  -n, --line-number         prefix each line with its line number
+  -c, --count               only print how many lines matched
+  -l, --files-with-matches  only print the file name if something matched
+  -w, --word-regexp         only match whole words
+  -A, --after-context=NUM   print NUM lines of context after every match
+  -B, --before-context=NUM  print NUM lines of context before every match
+  -C, --context=NUM         print NUM lines of context on both sides; -A and -B win over it
+  -j, --jobs=NUM            search NUM files at a time (default: one per CPU)
+  -r, --recursive           search the files in directories, and in theirs
+      --no-ignore           with -r, don't skip what .gitignore and .ignore files list
+      --color[=WHEN]        highlight matches: auto (the default), always or never
+      --json                print JSON Lines: begin, match, context and end records for every
+                            file with a match, and a summary at the end; not with -c or -l
+  -h, --help                print this help
+  -V, --version             print the version
+  --                        everything after this is QUERY or FILE, even if it starts with -
+
+Short options can be combined, e.g. -inw or -nC2.";
+
+//when to highlight the matches
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Color {
+    Auto,
+    Always,
+    Never,
+}
+
+//everything that can go wrong with the command line. Help and Version aren't really errors,
+//but like them they stop the parsing and main prints them instead of running
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArgsError {
+    Help,
+    Version,
+    MissingQuery,
+    UnknownFlag(String),
+    MissingValue(String),
+    BadValue { flag: String, value: String },
+    Conflict(String, String),
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgsError::Help => write!(f, "{}", USAGE),
+            ArgsError::Version => write!(f, "minigrep {}", env!("CARGO_PKG_VERSION")),
+            ArgsError::MissingQuery => write!(f, "Didn't get a query string"),
+            ArgsError::UnknownFlag(flag) => write!(f, "unknown option {}", flag),
+            ArgsError::MissingValue(flag) => write!(f, "{} needs a value", flag),
+            ArgsError::BadValue { flag, value } => write!(f, "invalid value '{}' for {}", value, flag),
+            ArgsError::Conflict(flag, other) => write!(f, "{} can't be used with {}", flag, other),
+        }
+    }
+}
+
+impl Error for ArgsError {}
+
+//`case_insensitive` is the default coming from the environment, -i and -s win over it
+pub fn parse(mut args: impl Iterator<Item = String>, case_insensitive: bool) -> Result<Config, ArgsError> {
+    let mut config = Config {
+        query: String::new(),
+        paths: Vec::new(),
+        case_insensitive,
+        smart_case: false,
+        regex: false,
+        invert_match: false,
+        line_number: false,
+        count: false,
+        files_with_matches: false,
+        word: false,
+        recursive: false,
+        no_ignore: false,
+        after_context: 0,
+        before_context: 0,
+        jobs: 1,
+        json: false,
+        color: Color::Auto,
+    };
+    let mut positional = Vec::new();
+    //the flags with a number, kept aside until the end because -C is only a default for
+    //-A and -B, whatever the order they come in
+    let mut numbers = Numbers::default();
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            positional.extend(args.by_ref());
+        } else if let Some(long) = arg.strip_prefix("--") {
+            let (name, value) = match long.find('=') {
+                Some(i) => (&long[..i], Some(&long[i + 1..])),
+                None => (long, None),
+            };
+            if name == "color" || name == "colour" {
+                config.color = match value {
+                    None | Some("auto") => Color::Auto,
+                    Some("always") => Color::Always,
+                    Some("never") => Color::Never,
+                    Some(value) => {
+                        return Err(ArgsError::BadValue {
+                            flag: String::from("--color"),
+                            value: value.to_string(),
+                        })
+                    }
+                };
+                continue;
+            }
+            let short = match name {
+                "after-context" => Some('A'),
+                "before-context" => Some('B'),
+                "context" => Some('C'),
+                "jobs" => Some('j'),
+                _ => None,
+            };
+            if let Some(short) = short {
+                let value = match value {
+                    Some(value) => value.to_string(),
+                    None => args.next().ok_or_else(|| ArgsError::MissingValue(format!("--{}", name)))?,
+                };
+                numbers.set(short, &format!("--{}", name), &value)?;
+                continue;
+            }
+            if value.is_some() {
+                return Err(ArgsError::UnknownFlag(arg));
+            }
+            //no short versions, so no point going through set
+            if name == "no-ignore" || name == "json" {
+                config.no_ignore |= name == "no-ignore";
+                config.json |= name == "json";
+                continue;
+            }
+            let short = match name {
+                "regex" => 'E',
+                "ignore-case" => 'i',
+                "case-sensitive" => 's',
+                "smart-case" => 'S',
+                "invert-match" => 'v',
+                "line-number" => 'n',
+                "count" => 'c',
+                "files-with-matches" => 'l',
+                "word-regexp" => 'w',
+                "recursive" => 'r',
+                "help" => 'h',
+                "version" => 'V',
+                _ => return Err(ArgsError::UnknownFlag(arg)),
+            };
+            set(&mut config, short)?;
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            //-inv is -i -n -v; a flag with a value takes the rest, -C2, or the next argument, -C 2
+            for (i, short) in arg[1..].char_indices() {
+                if let 'A' | 'B' | 'C' | 'j' = short {
+                    let flag = format!("-{}", short);
+                    let rest = &arg[2 + i..];
+                    let value = if rest.is_empty() {
+                        args.next().ok_or_else(|| ArgsError::MissingValue(flag.clone()))?
+                    } else {
+                        rest.to_string()
+                    };
+                    numbers.set(short, &flag, &value)?;
+                    break;
+                }
+                set(&mut config, short)?;
+            }
+        } else {
+            //a lone - is a path too (it will mean stdin)
+            positional.push(arg);
+        }
+    }
+
+    config.after_context = numbers.after.or(numbers.both).unwrap_or(0);
+    config.before_context = numbers.before.or(numbers.both).unwrap_or(0);
+    config.jobs = match numbers.jobs {
+        Some(jobs) => jobs,
+        None => thread::available_parallelism().map_or(1, |cpus| cpus.get()),
+    };
+
+    //--json has records for lines, not for counts or names
+    for &(set, flag) in &[(config.count, "-c"), (config.files_with_matches, "-l")] {
+        if config.json && set {
+            return Err(ArgsError::Conflict(String::from("--json"), String::from(flag)));
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    config.query = positional.next().ok_or(ArgsError::MissingQuery)?;
+    if config.smart_case {
+        config.case_insensitive = !has_uppercase(&config.query, config.regex);
+    }
+    config.paths = positional.map(PathBuf::from).collect();
+    if config.paths.is_empty() {
+        //like grep: standard input, or with -r the directory we're in
+        let default = if config.recursive { "." } else { "-" };
+        config.paths.push(PathBuf::from(default));
+    }
+    Ok(config)
+}
+
+#[derive(Default)]
+struct Numbers {
+    after: Option<usize>,
+    before: Option<usize>,
+    both: Option<usize>,
+    jobs: Option<usize>,
+}
+
+impl Numbers {
+    fn set(&mut self, short: char, flag: &str, value: &str) -> Result<(), ArgsError> {
+        let bad_value = || ArgsError::BadValue {
+            flag: flag.to_string(),
+            value: value.to_string(),
+        };
+        let number = value.parse().map_err(|_| bad_value())?;
+        match short {
+            'A' => self.after = Some(number),
+            'B' => self.before = Some(number),
+            'C' => self.both = Some(number),
+            //no work gets done with no workers
+            _ if number == 0 => return Err(bad_value()),
+            _ => self.jobs = Some(number),
+        }
+        Ok(())
+    }
+}
+
+fn set(config: &mut Config, short: char) -> Result<(), ArgsError> {
+    match short {
+        'E' => config.regex = true,
+        //the last of -i, -s and -S wins
+        'i' | 's' => {
+            config.case_insensitive = short == 'i';
+            config.smart_case = false;
+        }
+        'S' => config.smart_case = true,
+        'v' => config.invert_match = true,
+        'n' => config.line_number = true,
+        'c' => config.count = true,
+        'l' => config.files_with_matches = true,
+        'w' => config.word = true,
+        'r' => config.recursive = true,
+        'h' => return Err(ArgsError::Help),
+        'V' => return Err(ArgsError::Version),
+        _ => return Err(ArgsError::UnknownFlag(format!("-{}", short))),
+    }
+    Ok(())
+}
+
+//in a regex, \W or \S are classes rather than uppercase letters
+fn has_uppercase(query: &str, regex: bool) -> bool {
+    let mut escaped = false;
+    query.chars().any(|c| {
+        let upper = c.is_uppercase() && !escaped;
+        escaped = regex && c == '\\' && !escaped;
+        upper
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str], case_insensitive: bool) -> Result<Config, ArgsError> {
+        parse(args.iter().map(|arg| arg.to_string()), case_insensitive)
+    }
+
+    #[test]
+    fn positional_and_flags() {
+        let config = parse_args(&["-n", "body", "--invert-match", "poem.txt", "-cl"], false).unwrap();
+        assert_eq!("body", config.query);
+        assert_eq!(vec![PathBuf::from("poem.txt")], config.paths);
+        assert!(config.line_number && config.invert_match && config.count && config.files_with_matches);
+        assert!(!config.case_insensitive && !config.word && !config.regex);
+        assert_eq!(Color::Auto, config.color);
+    }
+
+    #[test]
+    fn combined_short_flags() {
+        let config = parse_args(&["-iwE", "to.", "poem.txt"], false).unwrap();
+        assert!(config.case_insensitive && config.word && config.regex);
+        assert_eq!(Err(ArgsError::UnknownFlag(String::from("-q"))), parse_args(&["-iq", "a", "b"], false).map(|_| ()));
+    }
+
+    #[test]
+    fn flags_override_the_environment() {
+        assert!(parse_args(&["to", "poem.txt"], true).unwrap().case_insensitive);
+        assert!(!parse_args(&["-s", "to", "poem.txt"], true).unwrap().case_insensitive);
+        assert!(parse_args(&["-i", "to", "poem.txt"], false).unwrap().case_insensitive);
+    }
+
+    #[test]
+    fn smart_case() {
+        assert!(parse_args(&["-S", "rust", "poem.txt"], false).unwrap().case_insensitive);
+        assert!(!parse_args(&["-S", "Rust", "poem.txt"], true).unwrap().case_insensitive);
+        assert!(parse_args(&["-S", "straße", "poem.txt"], false).unwrap().case_insensitive);
+        assert!(!parse_args(&["-S", "STRAẞE", "poem.txt"], false).unwrap().case_insensitive);
+        assert!(parse_args(&["-SE", r"\Wrust\S", "poem.txt"], false).unwrap().case_insensitive);
+        assert!(!parse_args(&["-S", r"\Wrust", "poem.txt"], false).unwrap().case_insensitive);
+        //a later -i or -s turns it off again
+        assert!(parse_args(&["-Si", "Rust", "poem.txt"], false).unwrap().case_insensitive);
+        assert!(!parse_args(&["-Ss", "rust", "poem.txt"], true).unwrap().case_insensitive);
+    }
+
+    #[test]
+    fn double_dash_ends_the_flags() {
+        let config = parse_args(&["-n", "--", "-v", "-"], false).unwrap();
+        assert_eq!("-v", config.query);
+        assert_eq!(vec![PathBuf::from("-")], config.paths);
+        assert!(!config.invert_match);
+    }
+
+    #[test]
+    fn many_paths_and_recursion() {
+        let config = parse_args(&["-r", "fn", "src", "tests", "--no-ignore"], false).unwrap();
+        assert!(config.recursive && config.no_ignore);
+        assert_eq!(vec![PathBuf::from("src"), PathBuf::from("tests")], config.paths);
+    }
+
+    #[test]
+    fn standard_input_by_default() {
+        assert_eq!(vec![PathBuf::from("-")], parse_args(&["-i", "a"], false).unwrap().paths);
+        assert_eq!(vec![PathBuf::from(".")], parse_args(&["-r", "a"], false).unwrap().paths);
+    }
+
+    #[test]
+    fn context_values() {
+        let config = parse_args(&["-nC2", "a", "-A", "5"], false).unwrap();
+        assert!(config.line_number);
+        assert_eq!((2, 5), (config.before_context, config.after_context));
+        let config = parse_args(&["--before-context=1", "a", "--context", "3"], false).unwrap();
+        assert_eq!((1, 3), (config.before_context, config.after_context));
+
+        let err = |args: &[&str]| parse_args(args, false).map(|_| ()).unwrap_err();
+        assert_eq!(ArgsError::MissingValue(String::from("-A")), err(&["a", "-A"]));
+        assert_eq!(
+            ArgsError::BadValue {
+                flag: String::from("-B"),
+                value: String::from("x")
+            },
+            err(&["-Bx", "a"])
+        );
+    }
+
+    #[test]
+    fn jobs() {
+        assert_eq!(3, parse_args(&["-rj3", "a"], false).unwrap().jobs);
+        assert_eq!(1, parse_args(&["--jobs=1", "a"], false).unwrap().jobs);
+        assert!(parse_args(&["a"], false).unwrap().jobs >= 1);
+        assert!(parse_args(&["-j", "0", "a"], false).is_err());
+    }
+
+    #[test]
+    fn json() {
+        assert!(parse_args(&["--json", "-n", "a"], false).unwrap().json);
+        assert_eq!(
+            ArgsError::Conflict(String::from("--json"), String::from("-l")),
+            parse_args(&["-l", "--json", "a"], false).map(|_| ()).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn color_values() {
+        assert_eq!(Color::Always, parse_args(&["--color=always", "a", "b"], false).unwrap().color);
+        assert_eq!(Color::Never, parse_args(&["--color=never", "a", "b"], false).unwrap().color);
+        assert_eq!(Color::Auto, parse_args(&["--color", "a", "b"], false).unwrap().color);
+        assert_eq!(
+            Err(ArgsError::BadValue {
+                flag: String::from("--color"),
+                value: String::from("sometimes")
+            }),
+            parse_args(&["--color=sometimes", "a", "b"], false).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let err = |args: &[&str]| parse_args(args, false).map(|_| ()).unwrap_err();
+        assert_eq!(ArgsError::Help, err(&["a", "--help"]));
+        assert_eq!(ArgsError::Version, err(&["-V"]));
+        assert_eq!(ArgsError::MissingQuery, err(&[]));
+        assert_eq!(ArgsError::UnknownFlag(String::from("--frobnicate")), err(&["--frobnicate", "a", "b"]));
+        assert_eq!(ArgsError::UnknownFlag(String::from("--count=3")), err(&["--count=3", "a", "b"]));
+    }
+}
@@ -0,0 +1,86 @@
+use std::env;
+use std::fmt::Display;
+use std::io::{self, IsTerminal, Write};
+
+use crate::Color;
+
+//grep's default colors
+pub const MATCH: &str = "\x1b[1;31m";
+pub const FILE_NAME: &str = "\x1b[35m";
+pub const LINE_NUMBER: &str = "\x1b[32m";
+pub const SEPARATOR: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+/// Writes text in a color, or plainly when colors are off.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Paint {
+    on: bool,
+}
+
+impl Paint {
+    /// Colors for `--color=always`; with auto, only for a terminal that can show them and
+    /// when NO_COLOR (https://no-color.org) isn't set.
+    pub fn detect(choice: Color) -> Paint {
+        let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        let dumb = env::var_os("TERM").is_some_and(|term| term == "dumb");
+        Paint::new(choice, io::stdout().is_terminal() && !dumb, no_color)
+    }
+
+    fn new(choice: Color, terminal: bool, no_color: bool) -> Paint {
+        let on = match choice {
+            Color::Always => true,
+            Color::Never => false,
+            //the flag is more specific than the environment, so only auto looks at it
+            Color::Auto => terminal && !no_color,
+        };
+        Paint { on }
+    }
+
+    pub fn is_on(self) -> bool {
+        self.on
+    }
+
+    pub fn write(self, out: &mut dyn Write, color: &str, text: impl Display) -> io::Result<()> {
+        if self.on {
+            write!(out, "{}{}{}", color, text, RESET)
+        } else {
+            write!(out, "{}", text)
+        }
+    }
+
+    /// `line` with the `spans` (byte ranges, in order) in the match color.
+    pub fn highlight(self, out: &mut dyn Write, line: &str, spans: &[(usize, usize)]) -> io::Result<()> {
+        let mut last = 0;
+        for &(begin, end) in spans {
+            write!(out, "{}", &line[last..begin])?;
+            self.write(out, MATCH, &line[begin..end])?;
+            last = end;
+        }
+        write!(out, "{}", &line[last..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn when_colors_are_on() {
+        assert!(Paint::new(Color::Always, false, true).is_on());
+        assert!(!Paint::new(Color::Never, true, false).is_on());
+        assert!(Paint::new(Color::Auto, true, false).is_on());
+        assert!(!Paint::new(Color::Auto, false, false).is_on());
+        assert!(!Paint::new(Color::Auto, true, true).is_on());
+    }
+
+    #[test]
+    fn highlights_spans() {
+        let mut out = Vec::new();
+        Paint { on: true }.highlight(&mut out, "to be or not to be", &[(0, 2), (13, 15)]).unwrap();
+        assert_eq!("\x1b[1;31mto\x1b[0m be or not \x1b[1;31mto\x1b[0m be", String::from_utf8(out).unwrap());
+
+        let mut out = Vec::new();
+        Paint { on: false }.highlight(&mut out, "to be", &[(0, 2)]).unwrap();
+        assert_eq!("to be", String::from_utf8(out).unwrap());
+    }
+}
@@ -0,0 +1,140 @@
+//Unicode simple case folding: every character maps to one character, so folding never changes
+//how many characters a piece of text has. That is what case-insensitive matching wants, and what
+//str::to_lowercase doesn't give: it turns İ into two characters, so "i" would match half of it.
+//Full folding (ß to "ss") would need exactly that, so ß only matches ß and ẞ here.
+
+//the characters whose simple folding isn't their lowercase, from Unicode's CaseFolding.txt
+const EXCEPTIONS: [(char, char); 25] = [
+    ('\u{b5}', '\u{3bc}'),   //micro sign to mu
+    ('\u{17f}', 's'),        //long s
+    ('\u{345}', '\u{3b9}'),  //combining ypogegrammeni to iota
+    ('\u{3c2}', '\u{3c3}'),  //final sigma
+    ('\u{3d0}', '\u{3b2}'),  //beta symbol
+    ('\u{3d1}', '\u{3b8}'),  //theta symbol
+    ('\u{3d5}', '\u{3c6}'),  //phi symbol
+    ('\u{3d6}', '\u{3c0}'),  //pi symbol
+    ('\u{3f0}', '\u{3ba}'),  //kappa symbol
+    ('\u{3f1}', '\u{3c1}'),  //rho symbol
+    ('\u{3f5}', '\u{3b5}'),  //lunate epsilon
+    ('\u{1e9b}', '\u{1e61}'), //long s with dot above
+    ('\u{1fbe}', '\u{3b9}'),  //prosgegrammeni to iota
+    ('\u{1fd3}', '\u{390}'),  //the same letter twice in Unicode
+    ('\u{1fe3}', '\u{3b0}'),
+    ('\u{fb05}', '\u{fb06}'), //the long s t ligature to the s t one
+    ('\u{1c80}', '\u{432}'),  //the Cyrillic rounded and tall letters to their plain ones
+    ('\u{1c81}', '\u{434}'),
+    ('\u{1c82}', '\u{43e}'),
+    ('\u{1c83}', '\u{441}'),
+    ('\u{1c84}', '\u{442}'),
+    ('\u{1c85}', '\u{442}'),
+    ('\u{1c86}', '\u{44a}'),
+    ('\u{1c87}', '\u{463}'),
+    ('\u{1c88}', '\u{a64b}'),
+];
+
+/// The simple case folding of `c`.
+pub fn fold(c: char) -> char {
+    if c.is_ascii() {
+        return c.to_ascii_lowercase();
+    }
+    if let Some(&(_, folded)) = EXCEPTIONS.iter().find(|&&(from, _)| from == c) {
+        return folded;
+    }
+    match c as u32 {
+        //Cherokee folds to its uppercase letters, the older ones
+        0xab70..=0xabbf => return char::from_u32(c as u32 - 0xab70 + 0x13a0).unwrap(),
+        0x13f8..=0x13fd => return char::from_u32(c as u32 - 8).unwrap(),
+        _ => {}
+    }
+    let mut lower = c.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(lower), None) => lower,
+        //İ lowercases to i and a combining dot, it has no simple folding
+        _ => c,
+    }
+}
+
+/// A query folded once, to be found in text ignoring case without folding (or allocating a
+/// copy of) the text itself.
+#[derive(Debug, Clone)]
+pub struct Folded {
+    chars: Vec<char>,
+    //the query in lowercase bytes, when it is all ASCII
+    ascii: Option<Vec<u8>>,
+}
+
+impl Folded {
+    pub fn new(query: &str) -> Folded {
+        Folded {
+            chars: query.chars().map(fold).collect(),
+            ascii: if query.is_ascii() { Some(query.to_ascii_lowercase().into_bytes()) } else { None },
+        }
+    }
+
+    /// The first match in `text` starting at byte `start` or later, as a byte range.
+    pub fn find_at(&self, text: &str, start: usize) -> Option<(usize, usize)> {
+        if self.chars.is_empty() {
+            return Some((start, start));
+        }
+        let rest = &text[start..];
+        //the fast path: ASCII query in ASCII text, bytes compared without any decoding. Some
+        //non-ASCII characters fold into ASCII (the Kelvin sign into k), so the text has to be too
+        if let Some(query) = &self.ascii {
+            if rest.is_ascii() {
+                return rest
+                    .as_bytes()
+                    .windows(query.len())
+                    .position(|window| window.iter().zip(query).all(|(a, b)| a.to_ascii_lowercase() == *b))
+                    .map(|i| (start + i, start + i + query.len()));
+            }
+        }
+        rest.char_indices()
+            .find_map(|(i, _)| self.len_at(&rest[i..]).map(|len| (start + i, start + i + len)))
+    }
+
+    //the length in bytes of the match at the very start of `text`, if there is one
+    fn len_at(&self, text: &str) -> Option<usize> {
+        let mut chars = text.chars();
+        let mut len = 0;
+        for &wanted in &self.chars {
+            let c = chars.next()?;
+            if fold(c) != wanted {
+                return None;
+            }
+            len += c.len_utf8();
+        }
+        Some(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simple_folding() {
+        assert_eq!('a', fold('A'));
+        assert_eq!('é', fold('É'));
+        assert_eq!('σ', fold('Σ'));
+        assert_eq!('σ', fold('ς'));
+        assert_eq!('ß', fold('ẞ'));
+        assert_eq!('k', fold('\u{212a}'));
+        assert_eq!('s', fold('ſ'));
+        assert_eq!('İ', fold('İ'));
+        assert_eq!('ı', fold('ı'));
+        assert_eq!('Ꭰ', fold('ꭰ'));
+    }
+
+    #[test]
+    fn finds_ignoring_case() {
+        assert_eq!(Some((6, 10)), Folded::new("rust").find_at("Trust RUST", 2));
+        assert_eq!(Some((0, 10)), Folded::new("ΣΟΦΟΣ").find_at("σοφος", 0));
+        //the Kelvin sign is three bytes, and makes the ASCII fast path step aside
+        assert_eq!(Some((0, 4)), Folded::new("ok").find_at("o\u{212a} ok", 0));
+        assert_eq!(Some((8, 10)), Folded::new("ẞ").find_at("strasse ß", 0));
+        //no dotted İ from i, and no "ss" for ß: neither is a simple folding
+        assert_eq!(None, Folded::new("i").find_at("İ", 0));
+        assert_eq!(None, Folded::new("ß").find_at("STRASSE", 0));
+        assert_eq!(Some((2, 2)), Folded::new("").find_at("abc", 2));
+    }
+}
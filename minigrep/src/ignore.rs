@@ -0,0 +1,207 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+//the files read in every directory we walk into, .ignore being the one for tools other than git
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+#[derive(Debug, Clone)]
+struct Rule {
+    //the directory of the ignore file, anchored patterns are relative to it
+    base: PathBuf,
+    pattern: String,
+    negated: bool,
+    dir_only: bool,
+    //a pattern with a / in it is matched against the whole relative path, otherwise
+    //against the file name at any depth
+    anchored: bool,
+}
+
+/// The .gitignore-style rules that apply in one directory: its own plus its parents'.
+#[derive(Debug, Clone, Default)]
+pub struct Ignore {
+    rules: Vec<Rule>,
+}
+
+impl Ignore {
+    /// The rules for `dir`, a subdirectory: ours, followed by the ones in `dir`'s ignore files.
+    pub fn child(&self, dir: &Path) -> Ignore {
+        let mut ignore = self.clone();
+        for name in IGNORE_FILES.iter() {
+            //no ignore file, or one we can't read, means no rules
+            if let Ok(text) = fs::read_to_string(dir.join(name)) {
+                ignore.add(dir, &text);
+            }
+        }
+        ignore
+    }
+
+    fn add(&mut self, base: &Path, text: &str) {
+        for line in text.lines() {
+            //trailing spaces don't count unless escaped, which we don't bother with
+            let mut line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let negated = line.starts_with('!');
+            if negated {
+                line = &line[1..];
+            }
+            //\# and \! are a literal # or ! at the start
+            if line.starts_with("\\#") || line.starts_with("\\!") {
+                line = &line[1..];
+            }
+            let dir_only = line.ends_with('/');
+            let line = line.trim_end_matches('/');
+            if line.is_empty() {
+                continue;
+            }
+            let anchored = line.contains('/');
+            self.rules.push(Rule {
+                base: base.to_path_buf(),
+                pattern: line.trim_start_matches('/').to_string(),
+                negated,
+                dir_only,
+                anchored,
+            });
+        }
+    }
+
+    /// Whether `path` should be skipped. Like git, the last rule that matches decides,
+    /// so a later `!pattern` can bring back something an earlier rule ignored.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        for rule in self.rules.iter().rev() {
+            if rule.dir_only && !is_dir {
+                continue;
+            }
+            let text = if rule.anchored {
+                match path.strip_prefix(&rule.base) {
+                    Ok(relative) => relative.to_string_lossy().replace('\\', "/"),
+                    Err(_) => continue,
+                }
+            } else {
+                match path.file_name() {
+                    Some(name) => name.to_string_lossy().into_owned(),
+                    None => continue,
+                }
+            };
+            if glob(rule.pattern.as_bytes(), text.as_bytes()) {
+                return !rule.negated;
+            }
+        }
+        false
+    }
+}
+
+//`*` and `?` stop at a /, `**` doesn't; `[a-z]` and `[!a-z]` are classes
+fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => match rest {
+            //a trailing ** is everything inside
+            [] => true,
+            //**/ is zero or more directories
+            [b'/', rest @ ..] => {
+                glob(rest, text) || text.iter().enumerate().any(|(i, &c)| c == b'/' && glob(rest, &text[i + 1..]))
+            }
+            _ => (0..=text.len()).any(|i| glob(rest, &text[i..])),
+        },
+        [b'*', rest @ ..] => (0..=text.len())
+            .take_while(|&i| i == 0 || text[i - 1] != b'/')
+            .any(|i| glob(rest, &text[i..])),
+        [b'?', rest @ ..] => match text {
+            [c, text @ ..] if *c != b'/' => glob(rest, text),
+            _ => false,
+        },
+        [b'[', class @ ..] => match class.iter().skip(1).position(|&c| c == b']') {
+            Some(end) => {
+                let (set, rest) = (&class[..end + 1], &class[end + 2..]);
+                match text {
+                    [c, text @ ..] if in_set(set, *c) => glob(rest, text),
+                    _ => false,
+                }
+            }
+            //no closing ], so the [ is just a character
+            None => text.first() == Some(&b'[') && glob(class, &text[1..]),
+        },
+        [b'\\', c, rest @ ..] => text.first() == Some(c) && glob(rest, &text[1..]),
+        [c, rest @ ..] => text.first() == Some(c) && glob(rest, &text[1..]),
+    }
+}
+
+fn in_set(set: &[u8], c: u8) -> bool {
+    let (negated, set) = match set {
+        [b'!', set @ ..] | [b'^', set @ ..] => (true, set),
+        _ => (false, set),
+    };
+    let mut found = false;
+    let mut i = 0;
+    while i < set.len() {
+        if i + 2 < set.len() && set[i + 1] == b'-' {
+            found |= set[i] <= c && c <= set[i + 2];
+            i += 3;
+        } else {
+            found |= set[i] == c;
+            i += 1;
+        }
+    }
+    found != negated && c != b'/'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        assert!(glob(b"*.rs", b"main.rs"));
+        assert!(!glob(b"*.rs", b"src/main.rs"));
+        assert!(glob(b"src/**/*.rs", b"src/main.rs"));
+        assert!(glob(b"src/**/*.rs", b"src/a/b/main.rs"));
+        assert!(glob(b"**/target", b"a/target"));
+        assert!(glob(b"target/**", b"target/debug/x"));
+        assert!(glob(b"file?.[ch]", b"file1.c"));
+        assert!(!glob(b"file?.[!ch]", b"file1.c"));
+        assert!(glob(b"[a-c]x", b"bx"));
+        assert!(glob(b"a[b", b"a[b"));
+        assert!(glob(b"\\*", b"*"));
+        assert!(!glob(b"\\*", b"x"));
+    }
+
+    #[test]
+    fn rules() {
+        let mut ignore = Ignore::default();
+        let base = Path::new("repo");
+        ignore.add(base, "# build output\n/target/\n*.log\n!keep.log\nbuild/\ndocs/*.html\n");
+
+        assert!(ignore.is_ignored(Path::new("repo/target"), true));
+        //anchored with the leading /, so only at the top
+        assert!(!ignore.is_ignored(Path::new("repo/src/target"), true));
+        //only directories
+        assert!(!ignore.is_ignored(Path::new("repo/build"), false));
+        assert!(ignore.is_ignored(Path::new("repo/a/build"), true));
+
+        assert!(ignore.is_ignored(Path::new("repo/src/debug.log"), false));
+        assert!(!ignore.is_ignored(Path::new("repo/src/keep.log"), false));
+        assert!(ignore.is_ignored(Path::new("repo/docs/index.html"), false));
+        assert!(!ignore.is_ignored(Path::new("repo/docs/api/index.html"), false));
+        assert!(!ignore.is_ignored(Path::new("repo/src/main.rs"), false));
+    }
+
+    #[test]
+    fn child_directories_add_rules() {
+        let dir = std::env::temp_dir().join(format!("minigrep-ignore-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join(".gitignore"), "*.tmp\n").unwrap();
+        fs::write(dir.join("sub/.ignore"), "!important.tmp\nnotes.txt\n").unwrap();
+
+        let top = Ignore::default().child(&dir);
+        let sub = top.child(&dir.join("sub"));
+        assert!(top.is_ignored(&dir.join("a.tmp"), false));
+        assert!(!top.is_ignored(&dir.join("notes.txt"), false));
+        assert!(sub.is_ignored(&dir.join("sub/a.tmp"), false));
+        assert!(!sub.is_ignored(&dir.join("sub/important.tmp"), false));
+        assert!(sub.is_ignored(&dir.join("sub/notes.txt"), false));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,52 @@
+use std::io::{self, Write};
+
+//just enough JSON for --json: strings and numbers, written straight out. The text is UTF-8
+//already (search.rs replaces anything that isn't), so only quotes, backslashes and control
+//characters need escaping.
+
+/// `text` as a JSON string, quotes included.
+pub fn write_str(out: &mut dyn Write, text: &str) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    let mut last = 0;
+    for (i, c) in text.char_indices() {
+        let escaped = match c {
+            '"' => "\\\"",
+            '\\' => "\\\\",
+            '\n' => "\\n",
+            '\r' => "\\r",
+            '\t' => "\\t",
+            //the rest of the control characters as \u00XX
+            c if (c as u32) < 0x20 || c == '\u{7f}' => "",
+            _ => continue,
+        };
+        write!(out, "{}", &text[last..i])?;
+        if escaped.is_empty() {
+            write!(out, "\\u{:04x}", c as u32)?;
+        } else {
+            out.write_all(escaped.as_bytes())?;
+        }
+        last = i + c.len_utf8();
+    }
+    write!(out, "{}", &text[last..])?;
+    out.write_all(b"\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(text: &str) -> String {
+        let mut out = Vec::new();
+        write_str(&mut out, text).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(r#""plain""#, string("plain"));
+        assert_eq!(r#""say \"hi\"\\n""#, string(r#"say "hi"\n"#));
+        assert_eq!(r#""a\tb\r\n""#, string("a\tb\r\n"));
+        assert_eq!(r#""\u0000\u001b[1m\u007f""#, string("\0\x1b[1m\x7f"));
+        assert_eq!(r#""σοφος ☃""#, string("σοφος ☃"));
+    }
+}
@@ -1,52 +1,73 @@
 use std::error::Error;
-use std::fs;
 use std::env;
+use std::io::{self, Write};
+use std::path::PathBuf;
 
+mod cli;
+mod color;
+mod fold;
+mod ignore;
+mod json;
+mod matcher;
+mod parallel;
+mod printer;
+mod regex;
+mod search;
+mod walk;
+
+pub use cli::{ArgsError, Color, USAGE};
+use color::Paint;
+use fold::Folded;
+use parallel::Output;
+use printer::Printer;
+pub use matcher::Matcher;
+pub use regex::{Regex, RegexError};
+pub use search::{Line, LineKind, Searcher};
+
+//one field per command line option, see cli.rs for the flags that set them
 pub struct Config {
     pub query: String,
-    pub file_name: String,
+    pub paths: Vec<PathBuf>,
     pub case_insensitive: bool,
+    //-S: ignore case unless the query has an uppercase letter, decided when parsing
+    pub smart_case: bool,
+    //-E or --regex: the query is a regular expression instead of plain text
+    pub regex: bool,
+    pub invert_match: bool,
+    pub line_number: bool,
+    pub count: bool,
+    pub files_with_matches: bool,
+    pub word: bool,
+    pub recursive: bool,
+    pub no_ignore: bool,
+    //-A, -B and -C: lines of context printed after and before every match
+    pub after_context: usize,
+    pub before_context: usize,
+    //-j: how many files are searched at the same time
+    pub jobs: usize,
+    //--json: JSON Lines instead of grep's output, see printer.rs for the records
+    pub json: bool,
+    pub color: Color,
 }
 
 impl Config {
-    //&'static str is the type of string literals, which is our error message type for now.
+    //&'static str was the type of string literals, which was our error message type at first.
+    //Now the errors are an enum, ArgsError, so main can tell --help apart from a real mistake.
 
     //The standard library documentation for the env::args function shows that the type of the iterator it returns is std::env::Args.
-    //Because we’re taking ownership of args and we’ll be mutating args by iterating over it, we can add the mut keyword into the specification of the args parameter to make it mutable.
-    pub fn new(mut args: std::env::Args) -> Result<Config, &'static str> {
-        if args.len() < 3 {
-           // panic!("not enough arguments, should be at least 3");
-            return Err("not enough arguments, should be at least 3");
-        }
-        //We could manage the String data in a number of different ways, but the easiest, though somewhat inefficient, route is to call the clone method on the values. 
-        //This will make a full copy of the data for the Config instance to own, which takes more time and memory than storing a reference to the string data. 
-        //However, cloning the data also makes our code very straightforward because we don’t have to manage the lifetimes of the references; 
-        //in this circumstance, giving up a little performance to gain simplicity is a worthwhile trade-off.
-        //let query = args[1].clone();
-        //let file_name = args[2].clone();
-        //Environment variable
-        //let case_insensitive = env::var("CASE_INSENSITIVE").is_err();
-               
-        //We needed clone here because we have a slice with String elements in the parameter args, now changed with iterators
+    //We take any iterator of Strings instead, env::args() is one and tests can pass a Vec's iterator.
+    //We needed clone when we had a slice with String elements in the parameter args, now changed with iterators
+    pub fn new(mut args: impl Iterator<Item = String>) -> Result<Config, ArgsError> {
         //since first element of the args is program name, we next iterator
         args.next();
 
-        let query = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a query string"),
-        }
+        //Environment variable, the default that -i and -s can override
+        let case_insensitive = env::var("CASE_INSENSITIVE").is_ok();
 
-        let file_name = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a file name string"),
-        }
-        let case_sensitive = env::var("CASE_INSENSITIVE").is_err();
-        println!("{}", case_sensitive);
-    
         //borrowed string can't be overwritten
         //args[1] = "balbal".to_string();
-    
-        Ok(Config {query, file_name, case_sensitive})
+
+        cli::parse(args, case_insensitive)
     }
 }
 
@@ -55,7 +76,7 @@ impl Config {
 //run function now returns an Ok value in the success case. We’ve declared the run function’s success type as () in the signature,
 //which means we need to wrap the unit type value in the Ok value.  This Ok(()) syntax might look a bit strange at first, 
 //but using () like this is the idiomatic way to indicate that we’re calling run for its side effects only; it doesn’t return a value we need.
-pub fn run(config: Config) -> Result<(), Box<dyn Error>>{
+pub fn run(config: Config) -> Result<bool, Box<dyn Error>>{
     /* 
         let contents = fs::read_to_string(config.file_name)
             .expect("Something went wrong while reading the file");
@@ -64,26 +85,102 @@ pub fn run(config: Config) -> Result<(), Box<dyn Error>>{
     //Instead of allowing the program to panic by calling expect, the run function will return a Result<T, E> when something goes wrong. 
     //This will let us further consolidate into main the logic around handling errors in a user-friendly way.
      
-    let contents = fs::read_to_string(config.file_name)?;
-    //println!("With text:\n{}", contents);
-    
-    let result = if config.case_insensitive {
-        search_insensitive(&config.query, &contents)
+    //a bad pattern is reported like any other error, through the ? operator
+    let matcher = Matcher::new(&config)?;
+
+    let printer = Printer::new(&config, &matcher, Paint::detect(config.color));
+    //the walk goes on while files are searched; the first two tell whether there is more than one
+    let mut files = walk::files(&config.paths, config.recursive, !config.no_ignore);
+    let first: Vec<_> = files.by_ref().take(2).collect();
+    let several = first.len() > 1;
+    let files = first.into_iter().chain(files);
+    //locked once for the whole run instead of once per println!
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut progress = Progress::default();
+
+    if config.jobs <= 1 || !several {
+        //straight to stdout, so a big file or a pipe is printed while it's read
+        for file in files {
+            //with context, groups are separated by --, also from one file to the next
+            let result = printer.file(&file, progress.matched, &mut out);
+            if !progress.record(result) {
+                break;
+            }
+        }
     } else {
-        search(&config.query, &contents)  
-    };
+        //the file being printed streams to stdout while the next few are searched into bounded
+        //buffers, so the output is the same whatever finishes first
+        let search = |file: &io::Result<PathBuf>, out: &mut dyn Write| printer.file(file, false, out);
+        //whether the file being printed has printed anything yet
+        let mut started = false;
+        parallel::in_order(files, config.jobs, search, |output| match output {
+            Output::Bytes(bytes) => {
+                let mut written = Ok(());
+                if printer.context() && progress.matched && !started {
+                    written = printer.separator(&mut out);
+                }
+                started = true;
+                match written.and_then(|_| out.write_all(&bytes)) {
+                    Ok(()) => true,
+                    Err(err) => progress.record(Err(err)),
+                }
+            }
+            Output::Done(result) => {
+                started = false;
+                progress.record(result)
+            }
+        });
+    }
+
+    //only --json has a summary, and nobody is left to read it after a broken pipe
+    match printer.summary(progress.files, progress.files_with_matches, progress.lines, &mut out) {
+        Err(err) if err.kind() != io::ErrorKind::BrokenPipe => return Err(err.into()),
+        _ => {}
+    }
 
-    
+    //nothing goes to stdout but results: no match is said with the exit code, as grep does
+    if progress.failed {
+        return Err("some files could not be searched".into());
+    }
+    Ok(progress.matched)
+}
 
-    if result.len() == 0 {
-        println!("No text matched for the string {}", config.query);
-    } 
-    else {
-        for line in result {
-            println!("{}", line);
+#[derive(Default)]
+struct Progress {
+    matched: bool,
+    failed: bool,
+    //the totals for --json's summary
+    files: usize,
+    files_with_matches: usize,
+    lines: usize,
+}
+
+impl Progress {
+    //one unreadable file shouldn't stop the search in the others, so errors are only printed;
+    //false means stop
+    fn record(&mut self, result: io::Result<usize>) -> bool {
+        match result {
+            Ok(lines) => {
+                self.files += 1;
+                self.lines += lines;
+                if lines > 0 {
+                    self.files_with_matches += 1;
+                    self.matched = true;
+                }
+            }
+            //whoever reads our output has gone, like `| head` does; nothing left to do
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {
+                self.matched = true;
+                return false;
+            }
+            Err(err) => {
+                eprintln!("minigrep: {}", err);
+                self.failed = true;
+            }
         }
+        true
     }
-    Ok(())
 }
 
 //lifetime parameters specify which argument lifetime is connected to the lifetime of the return value.
@@ -117,17 +214,21 @@ pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
 
 
 
+//This used to call to_lowercase on the query and on every line, a new String per line, and
+//lowercasing isn't the right comparison anyway: İ becomes two characters. Now the query is
+//folded once, see fold.rs, and compared with the line character by character.
 pub fn search_insensitive<'a> (query: &str, contents: &'a str) -> Vec<&'a str>{
-    let mut results = Vec::new();
-    let query = query.to_lowercase();
-
-    for line in contents.lines(){
-        if line.to_lowercase().contains(&query) {
-            results.push(line);
-        }
-    }
+    let query = Folded::new(query);
+    contents.lines()
+        .filter(|line| query.find_at(line, 0).is_some())
+        .collect()
+}
 
-    results
+//same shape as search, the pattern is compiled once by the caller and reused for every line
+pub fn search_regex<'a>(regex: &Regex, contents: &'a str) -> Vec<&'a str> {
+    contents.lines()
+        .filter(|line| regex.is_match(line))
+        .collect()
 }
 
 #[cfg(test)]
@@ -154,5 +255,21 @@ RusT:
 Safe, faSt, proDuctive.
 Pick tHree.";
         assert_eq!(vec!["Safe, faSt, proDuctive."], search_insensitive(query, contents));
+        assert_eq!(vec!["ΣΟΦΟΣ"], search_insensitive("σοφος", "σοφία\nΣΟΦΟΣ"));
+    }
+
+    #[test]
+    fn regex_search() {
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
+
+        let regex = Regex::new("^(Rust|Pick) ?[a-z]*[:.]$").unwrap();
+        assert_eq!(vec!["Rust:", "Pick three."], search_regex(&regex, contents));
+
+        let regex = Regex::new(r"\bRUST\b").unwrap().case_insensitive(true);
+        assert_eq!(vec!["Rust:"], search_regex(&regex, contents));
     }
 }
@@ -3,7 +3,7 @@
 use std::env;
 use std::process;
 
-use minigrep::Config;
+use minigrep::{ArgsError, Config};
 
 /*
 Rust community has developed a process to use as a guideline for splitting the separate concerns of a binary program when main starts getting large. 
@@ -40,16 +40,25 @@ fn main() {
     //The env::args function returns an iterator! Rather than collecting the iterator values into a vector and then passing a slice to Config::new,
     // now we’re passing ownership of the iterator returned from env::args to Config::new directly.
     let config = Config::new(env::args()).unwrap_or_else(|err|{
+        //--help and --version come back as errors too, but they are what the user asked for
+        if let ArgsError::Help | ArgsError::Version = err {
+            println!("{}", err);
+            process::exit(0);
+        }
         eprintln!("Problem parsing arguments: {}", err);
-        process::exit(1);
-    }) 
-        
-    )
+        eprintln!("Try 'minigrep --help' for more information.");
+        process::exit(2);
+    });
     
-    if let Err(e) = minigrep::run(config) {
-        eprintln!("Application error: {}", e);
-        
-        process::exit(1);
-    }   
+    //the same exit codes as grep, so scripts can tell "nothing found" (1) from "broken" (2)
+    match minigrep::run(config) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("Application error: {}", e);
+
+            process::exit(2);
+        }
+    }
 
 }
@@ -0,0 +1,133 @@
+use crate::fold::Folded;
+use crate::regex::{Regex, RegexError};
+use crate::Config;
+
+//what a line is tested with: plain text where we can, our regex engine otherwise
+enum Kind {
+    Literal(String),
+    Folded(Folded),
+    Regex(Regex),
+}
+
+/// Finds the query in a line, the way the command line asked for it: as text or as a
+/// regular expression, ignoring case or not, anywhere or only as a whole word.
+pub struct Matcher {
+    kind: Kind,
+    word: bool,
+}
+
+impl Matcher {
+    pub fn new(config: &Config) -> Result<Matcher, RegexError> {
+        let kind = if config.regex {
+            Kind::Regex(
+                Regex::new(&config.query)?
+                    .case_insensitive(config.case_insensitive)
+                    .whole_word(config.word),
+            )
+        } else if config.case_insensitive {
+            Kind::Folded(Folded::new(&config.query))
+        } else {
+            Kind::Literal(config.query.clone())
+        };
+        Ok(Matcher { kind, word: config.word })
+    }
+
+    pub fn is_match(&self, line: &str) -> bool {
+        self.find(line).is_some()
+    }
+
+    /// The first match in `line` as a byte range.
+    pub fn find(&self, line: &str) -> Option<(usize, usize)> {
+        self.find_from(line, 0)
+    }
+
+    /// Every match in `line`, left to right and without overlaps. Empty matches are left out,
+    /// there is nothing in them to show.
+    pub fn find_all(&self, line: &str) -> Vec<(usize, usize)> {
+        let mut matches = Vec::new();
+        let mut start = 0;
+        while let Some((begin, end)) = self.find_from(line, start) {
+            if end > begin {
+                matches.push((begin, end));
+                start = end;
+            } else {
+                //the same empty match would come back, step over a character
+                match line[end..].chars().next() {
+                    Some(c) => start = end + c.len_utf8(),
+                    None => break,
+                }
+            }
+        }
+        matches
+    }
+
+    fn find_from(&self, line: &str, mut start: usize) -> Option<(usize, usize)> {
+        while let Some((begin, end)) = self.find_at(line, start) {
+            //the regex engine checks for whole words itself, trying every match at each start
+            if !self.word || matches!(self.kind, Kind::Regex(_)) || is_whole_word(line, begin, end) {
+                return Some((begin, end));
+            }
+            //text matches the same way wherever it starts, so like grep -w, try one character on
+            start = begin + line[begin..].chars().next().map_or(1, char::len_utf8);
+            if start > line.len() {
+                break;
+            }
+        }
+        None
+    }
+
+    fn find_at(&self, line: &str, start: usize) -> Option<(usize, usize)> {
+        match &self.kind {
+            Kind::Literal(query) => line[start..].find(query.as_str()).map(|i| (start + i, start + i + query.len())),
+            Kind::Folded(query) => query.find_at(line, start),
+            Kind::Regex(regex) => regex.find_at(line, start),
+        }
+    }
+}
+
+//a whole word has no word character right before or right after it
+fn is_whole_word(line: &str, begin: usize, end: usize) -> bool {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    !line[..begin].chars().next_back().is_some_and(is_word) && !line[end..].chars().next().is_some_and(is_word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(args: &[&str]) -> Matcher {
+        let args = args.iter().map(|arg| arg.to_string()).chain(vec![String::from("file")]);
+        Matcher::new(&crate::cli::parse(args, false).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn literal_and_insensitive() {
+        assert_eq!(Some((4, 7)), matcher(&["a.c"]).find("abc a.c"));
+        assert_eq!(Some((2, 6)), matcher(&["-i", "rust"]).find("I RuSt"));
+        //İ lowercases to two characters; the offsets still point into the original line
+        assert_eq!(Some((3, 7)), matcher(&["-i", "rust"]).find("İ rust"));
+        assert_eq!(Some((0, 3)), matcher(&["-i", "ǆ\u{212a}"]).find("ǅk"));
+    }
+
+    #[test]
+    fn all_matches() {
+        assert_eq!(vec![(0, 2), (6, 8)], matcher(&["-w", "to"]).find_all("to do to"));
+        assert_eq!(vec![(1, 2), (3, 5)], matcher(&["-E", "b*"]).find_all("abcbb"));
+        assert_eq!(Vec::<(usize, usize)>::new(), matcher(&[""]).find_all("abc"));
+        assert_eq!(vec![(0, 2), (3, 5)], matcher(&["-i", "é"]).find_all("É é"));
+    }
+
+    #[test]
+    fn whole_words() {
+        let word = matcher(&["-w", "to"]);
+        assert_eq!(Some((10, 12)), word.find("tomorrow, to be"));
+        assert!(!word.is_match("into the toad"));
+        assert!(word.is_match("to"));
+        assert_eq!(Some((4, 9)), matcher(&["-wE", "[a-z]+!"]).find("Hey bang!"));
+        assert!(!matcher(&["-w", ""]).is_match("word"));
+        //the first branch is no whole word here, the second one is
+        assert_eq!(Some((0, 2)), matcher(&["-wE", "a|ab"]).find("ab"));
+        assert_eq!(Some((4, 6)), matcher(&["-wE", "x+"]).find("xxy xx"));
+        assert!(!matcher(&["-wE", "a|ab"]).is_match("abc"));
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::Mutex;
+use std::thread;
+
+//output is passed on in pieces this big
+const CHUNK: usize = 8 * 1024;
+//pieces a file can have written ahead of being printed, after that its worker waits its turn
+const CHUNKS_AHEAD: usize = 16;
+
+/// What `in_order` hands on for an item: what `work` wrote, piece by piece, then its result.
+pub enum Output<R> {
+    Bytes(Vec<u8>),
+    Done(R),
+}
+
+/// Runs `work` on every item, on up to `jobs` threads at once, and hands what it writes and
+/// then its result to `done` in the order of `items`. Items are taken from the iterator as
+/// there is room for them, so it can be a lazy one, like a directory walk. The item being handed on is streamed as
+/// it's searched; the ones after it are held back in bounded buffers, and no more than two per
+/// job are started ahead of it, so memory stays the same however big or slow one of them is.
+/// `done` returns false to stop; items not started yet are then skipped.
+///
+/// With one job everything runs on the calling thread, one item after the other.
+pub fn in_order<T, R>(
+    items: impl IntoIterator<Item = T>,
+    jobs: usize,
+    work: impl Fn(&T, &mut dyn Write) -> R + Sync,
+    mut done: impl FnMut(Output<R>) -> bool,
+) where
+    T: Send,
+    R: Send,
+{
+    if jobs <= 1 {
+        for item in items {
+            let mut buffer = Vec::new();
+            let result = work(&item, &mut buffer);
+            if (!buffer.is_empty() && !done(Output::Bytes(buffer))) || !done(Output::Done(result)) {
+                return;
+            }
+        }
+        return;
+    }
+
+    let window = jobs * 2;
+    let stop = AtomicBool::new(false);
+    //items go out in order with a channel of their own for the output, the next one as soon
+    //as a worker is free; workers share the receiving end
+    let (queue, jobs_queue) = mpsc::channel::<(T, SyncSender<Output<R>>)>();
+    let jobs_queue = Mutex::new(jobs_queue);
+    thread::scope(|scope| {
+        //owned here, so that returning closes the queue before the workers are waited for
+        let queue = queue;
+        for _ in 0..jobs {
+            let (jobs_queue, stop, work) = (&jobs_queue, &stop, &work);
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let job = jobs_queue.lock().unwrap().recv();
+                    let (item, sender) = match job {
+                        Ok(job) => job,
+                        Err(_) => break,
+                    };
+                    let mut out = Chunks {
+                        sender,
+                        buffer: Vec::new(),
+                    };
+                    let result = work(&item, &mut out);
+                    if out.flush().is_err() || out.sender.send(Output::Done(result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        //the receivers of the items handed out, the one being printed first
+        let mut pending = VecDeque::new();
+        let mut items = items.into_iter();
+        for item in items.by_ref().take(window) {
+            let (sender, receiver) = mpsc::sync_channel(CHUNKS_AHEAD);
+            queue.send((item, sender)).unwrap();
+            pending.push_back(receiver);
+        }
+        while let Some(receiver) = pending.pop_front() {
+            //a worker that panicked drops its sender, that item has nothing more to give
+            for output in receiver.iter() {
+                let last = matches!(output, Output::Done(_));
+                if !done(output) {
+                    //returning drops the queue and the receivers, which unblocks every worker
+                    stop.store(true, Ordering::Relaxed);
+                    return;
+                }
+                if last {
+                    break;
+                }
+            }
+            if let Some(item) = items.next() {
+                let (sender, receiver) = mpsc::sync_channel(CHUNKS_AHEAD);
+                queue.send((item, sender)).unwrap();
+                pending.push_back(receiver);
+            }
+        }
+    });
+}
+
+//what a worker writes an item's output to; sends wait while the item is too far ahead
+struct Chunks<R> {
+    sender: SyncSender<Output<R>>,
+    buffer: Vec<u8>,
+}
+
+impl<R> Write for Chunks<R> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = mem::take(&mut self.buffer);
+        //the receiving end is gone once in_order stops, like a closed pipe
+        self.sender
+            .send(Output::Bytes(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "output closed"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    #[test]
+    fn results_come_in_order() {
+        let items: Vec<u64> = (0..50).collect();
+        for &jobs in &[1, 4, 64] {
+            let mut seen = Vec::new();
+            let mut written = Vec::new();
+            //the early items are the slow ones, so they finish last
+            in_order(
+                items.clone(),
+                jobs,
+                |&i, out| {
+                    thread::sleep(Duration::from_millis((50 - i) / 10));
+                    write!(out, "{} ", i).unwrap();
+                    i * 2
+                },
+                |output| {
+                    match output {
+                        Output::Bytes(bytes) => written.extend(bytes),
+                        Output::Done(result) => seen.push(result),
+                    }
+                    true
+                },
+            );
+            assert_eq!(items.iter().map(|i| i * 2).collect::<Vec<_>>(), seen);
+            let expected: String = items.iter().map(|i| format!("{} ", i)).collect();
+            assert_eq!(expected, String::from_utf8(written).unwrap());
+        }
+    }
+
+    #[test]
+    fn stays_within_the_window() {
+        let first_done = AtomicBool::new(false);
+        let furthest = AtomicUsize::new(0);
+        in_order(
+            0..100,
+            4,
+            |&i, out| {
+                if i == 0 {
+                    //a lot of output from a slow file, more than its buffers can take
+                    for _ in 0..100 {
+                        out.write_all(&[b'x'; CHUNK]).unwrap();
+                    }
+                    thread::sleep(Duration::from_millis(50));
+                    first_done.store(true, Ordering::Relaxed);
+                } else if !first_done.load(Ordering::Relaxed) {
+                    furthest.fetch_max(i, Ordering::Relaxed);
+                }
+            },
+            |_| true,
+        );
+        assert!(furthest.load(Ordering::Relaxed) < 8);
+    }
+
+    #[test]
+    fn stops_when_asked() {
+        let started = AtomicUsize::new(0);
+        let taken = AtomicUsize::new(0);
+        let mut seen = Vec::new();
+        in_order(
+            (0..1000).inspect(|_| {
+                taken.fetch_add(1, Ordering::Relaxed);
+            }),
+            4,
+            |&i, _| {
+                started.fetch_add(1, Ordering::Relaxed);
+                thread::sleep(Duration::from_millis(1));
+                i
+            },
+            |output| {
+                if let Output::Done(result) = output {
+                    seen.push(result);
+                }
+                seen.len() < 3
+            },
+        );
+        assert_eq!(vec![0, 1, 2], seen);
+        assert!(started.load(Ordering::Relaxed) < 1000);
+        //nor are the items after the window taken from the iterator
+        assert!(taken.load(Ordering::Relaxed) <= 3 + 4 * 2);
+    }
+}
@@ -0,0 +1,229 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crate::color::{self, Paint};
+use crate::{json, walk, Config, Line, LineKind, Matcher, Searcher};
+
+/// Searches files and prints what the config asks for about them, the way grep does, or as
+/// JSON Lines with `--json`.
+pub struct Printer<'a> {
+    config: &'a Config,
+    matcher: &'a Matcher,
+    //with more than one file, every line says which file it came from
+    with_file_name: bool,
+    paint: Paint,
+}
+
+impl<'a> Printer<'a> {
+    pub fn new(config: &'a Config, matcher: &'a Matcher, paint: Paint) -> Printer<'a> {
+        Printer {
+            config,
+            matcher,
+            with_file_name: config.paths.len() > 1 || config.recursive,
+            paint,
+        }
+    }
+
+    //-c and -l don't print lines, so there's no context to read for them
+    pub fn context(&self) -> bool {
+        let config = self.config;
+        !config.count && !config.files_with_matches && (config.before_context > 0 || config.after_context > 0)
+    }
+
+    /// The `--` between groups of lines when there is context. JSON has line numbers instead.
+    pub fn separator(&self, out: &mut dyn Write) -> io::Result<()> {
+        if self.config.json {
+            return Ok(());
+        }
+        self.paint.write(out, color::SEPARATOR, "--")?;
+        writeln!(out)
+    }
+
+    /// Searches one of walk::files' results and says how many lines were selected, with the
+    /// file name in any error. `separate` starts the output with `--`, for the groups
+    /// printed before this file.
+    pub fn file(&self, file: &io::Result<PathBuf>, separate: bool, out: &mut dyn Write) -> io::Result<usize> {
+        match file {
+            Ok(path) => self
+                .search(path, separate, out)
+                .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", display_name(path), err))),
+            //io::Error can't be cloned, this keeps what gets printed
+            Err(err) => Err(io::Error::new(err.kind(), err.to_string())),
+        }
+    }
+
+    fn search(&self, path: &Path, mut separate: bool, out: &mut dyn Write) -> io::Result<usize> {
+        let config = self.config;
+        //fs::read_to_string would need the whole file in memory, and valid UTF-8 on top
+        let mut reader: Box<dyn BufRead> = if path == Path::new("-") {
+            Box::new(io::stdin().lock())
+        } else {
+            Box::new(BufReader::new(File::open(path)?))
+        };
+        if walk::is_binary(reader.fill_buf()?) {
+            return Ok(0);
+        }
+
+        let name = display_name(path);
+        let context = self.context();
+        let searcher = Searcher::new(self.matcher).invert(config.invert_match);
+        let searcher = if context { searcher.context(config.before_context, config.after_context) } else { searcher };
+
+        let mut count = 0;
+        let mut last = None;
+        searcher.search(reader, |line| {
+            if line.kind == LineKind::Match {
+                count += 1;
+            }
+            if config.files_with_matches {
+                //one match is all -l needs, stop reading
+                return Ok(false);
+            }
+            if config.count {
+                return Ok(true);
+            }
+            if config.json {
+                //begin only once there is something to say about the file
+                if last.is_none() {
+                    self.json_begin(&name, out)?;
+                }
+                last = Some(line.number);
+                self.json_line(&name, line, out)?;
+                return Ok(true);
+            }
+            //a gap in the line numbers starts a new group
+            if context && last.map_or(separate, |last| line.number > last + 1) {
+                self.separator(out)?;
+            }
+            last = Some(line.number);
+            separate = false;
+            self.line(&name, line, out)?;
+            Ok(true)
+        })?;
+
+        if config.json && last.is_some() {
+            self.json_end(&name, count, out)?;
+        } else if config.files_with_matches && count > 0 {
+            self.paint.write(out, color::FILE_NAME, &name)?;
+            writeln!(out)?;
+        } else if config.count && !config.files_with_matches {
+            if self.with_file_name {
+                self.paint.write(out, color::FILE_NAME, &name)?;
+                self.paint.write(out, color::SEPARATOR, ':')?;
+            }
+            writeln!(out, "{}", count)?;
+        }
+        Ok(count)
+    }
+
+    fn line(&self, name: &str, line: &Line, out: &mut dyn Write) -> io::Result<()> {
+        //like grep, matches say file:12:text and context lines file-12-text
+        let sep = if line.kind == LineKind::Match { ':' } else { '-' };
+        if self.with_file_name {
+            self.paint.write(out, color::FILE_NAME, name)?;
+            self.paint.write(out, color::SEPARATOR, sep)?;
+        }
+        if self.config.line_number {
+            self.paint.write(out, color::LINE_NUMBER, line.number)?;
+            self.paint.write(out, color::SEPARATOR, sep)?;
+        }
+        //only selected lines have matches to show; with -v they have none by definition
+        if self.paint.is_on() && line.kind == LineKind::Match && !self.config.invert_match {
+            self.paint.highlight(out, &line.text, &self.matcher.find_all(&line.text))?;
+        } else {
+            out.write_all(line.text.as_bytes())?;
+        }
+        writeln!(out)
+    }
+}
+
+//the --json records, one object per line; every one has a "type" saying which it is:
+//  {"type":"begin","path":"src/lib.rs"}
+//  {"type":"match","path":"src/lib.rs","line_number":3,"offset":58,"text":"...",
+//   "submatches":[{"start":4,"end":9,"text":"..."}]}
+//  {"type":"context", the same as match with no submatches}
+//  {"type":"end","path":"src/lib.rs","matched_lines":1}
+//and a summary after all the files, written by summary below. Offsets are in bytes of the
+//file: "offset" from its start to the line, the submatches' from the start of the line. Text
+//that isn't UTF-8 has its bad bytes replaced in "text", the offsets still count the originals.
+impl<'a> Printer<'a> {
+    fn json_begin(&self, name: &str, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(b"{\"type\":\"begin\",\"path\":")?;
+        json::write_str(out, name)?;
+        out.write_all(b"}\n")
+    }
+
+    fn json_line(&self, name: &str, line: &Line, out: &mut dyn Write) -> io::Result<()> {
+        let kind = if line.kind == LineKind::Match { "match" } else { "context" };
+        write!(out, "{{\"type\":\"{}\",\"path\":", kind)?;
+        json::write_str(out, name)?;
+        write!(out, ",\"line_number\":{},\"offset\":{},\"text\":", line.number, line.offset)?;
+        json::write_str(out, &line.text)?;
+        out.write_all(b",\"submatches\":[")?;
+        //as with colors, -v lines and context lines have nothing matched in them
+        if line.kind == LineKind::Match && !self.config.invert_match {
+            for (i, &(start, end)) in self.matcher.find_all(&line.text).iter().enumerate() {
+                if i > 0 {
+                    out.write_all(b",")?;
+                }
+                write!(out, "{{\"start\":{},\"end\":{},\"text\":", line.raw_offset(start), line.raw_offset(end))?;
+                json::write_str(out, &line.text[start..end])?;
+                out.write_all(b"}")?;
+            }
+        }
+        out.write_all(b"]}\n")
+    }
+
+    fn json_end(&self, name: &str, count: usize, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(b"{\"type\":\"end\",\"path\":")?;
+        json::write_str(out, name)?;
+        writeln!(out, ",\"matched_lines\":{}}}", count)
+    }
+
+    /// The last --json record, with the totals of the whole run.
+    pub fn summary(&self, files: usize, files_with_matches: usize, lines: usize, out: &mut dyn Write) -> io::Result<()> {
+        if !self.config.json {
+            return Ok(());
+        }
+        writeln!(
+            out,
+            "{{\"type\":\"summary\",\"files_searched\":{},\"files_with_matches\":{},\"matched_lines\":{}}}",
+            files, files_with_matches, lines
+        )
+    }
+}
+
+//what grep calls standard input in its output
+fn display_name(path: &Path) -> String {
+    if path == Path::new("-") {
+        String::from("(standard input)")
+    } else {
+        path.display().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Color;
+    use std::fs;
+
+    #[test]
+    fn json_offsets_count_the_bytes_in_the_file() {
+        let path = std::env::temp_dir().join(format!("minigrep-printer-{}", std::process::id()));
+        fs::write(&path, b"ok\na\xff\xfeb ERROR\n").unwrap();
+        let args = vec!["minigrep", "--json", "-E", "ERROR", path.to_str().unwrap()];
+        let config = Config::new(args.into_iter().map(String::from)).unwrap();
+        let matcher = Matcher::new(&config).unwrap();
+
+        let mut out = Vec::new();
+        let printer = Printer::new(&config, &matcher, Paint::detect(Color::Never));
+        assert_eq!(1, printer.file(&Ok(path.clone()), false, &mut out).unwrap());
+        let out = String::from_utf8(out).unwrap();
+        //the two bad bytes show as two U+FFFD, 6 bytes, but ERROR is at byte 5 of the line
+        assert!(out.contains("\"offset\":3,\"text\":\"a\u{fffd}\u{fffd}b ERROR\""), "{}", out);
+        assert!(out.contains("{\"start\":5,\"end\":10,\"text\":\"ERROR\"}"), "{}", out);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,808 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::fold::fold;
+
+//a small regular expression engine: the pattern is parsed into a tree, compiled into a list of
+//instructions and run as a Pike VM, i.e. all the alternatives are followed at the same time, one
+//character at a time. Unlike backtracking there is no pattern that takes exponential time,
+//a search is O(pattern length * text length).
+//
+//supported: literals, `.`, classes like `[a-z_]` and `[^0-9]`, `\d \w \s` (and `\D \W \S`),
+//anchors `^ $`, word boundaries `\b \B`, alternation `|`, groups `( )` and `(?: )`,
+//repetition `* + ? {n} {n,} {n,m}`, with a trailing `?` for the lazy versions.
+
+//bounded repetition is compiled by copying the repeated part, so keep the copies in check
+const MAX_REPEAT: u32 = 1000;
+//copies of copies multiply, so the whole program is limited too
+const MAX_PROGRAM: usize = 100_000;
+//the parser and the compiler recurse into groups and repetitions, this keeps them off the end
+//of the stack
+const MAX_DEPTH: usize = 250;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegexError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid pattern at position {}: {}", self.position, self.message)
+    }
+}
+
+impl Error for RegexError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Perl {
+    Digit,
+    Word,
+    Space,
+}
+
+impl Perl {
+    fn matches(self, c: char) -> bool {
+        match self {
+            Perl::Digit => c.is_ascii_digit(),
+            Perl::Word => is_word(c),
+            Perl::Space => c.is_whitespace(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ClassItem {
+    Range(char, char),
+    Perl(Perl, bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Class {
+    items: Vec<ClassItem>,
+    negated: bool,
+}
+
+impl Class {
+    fn matches(&self, c: char) -> bool {
+        let found = self.items.iter().any(|item| match *item {
+            ClassItem::Range(low, high) => low <= c && c <= high,
+            ClassItem::Perl(perl, negated) => perl.matches(c) != negated,
+        });
+        found != self.negated
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Empty,
+    Char(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    WordBoundary(bool),
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    },
+}
+
+struct Parser<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    pattern: &'a str,
+    //groups open around pos
+    depth: usize,
+    //the instructions the node just parsed compiles to, and how deeply it nests
+    size: usize,
+    height: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, RegexError> {
+        Err(RegexError {
+            position: self.pos,
+            message: message.into(),
+        })
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse(mut self) -> Result<Node, RegexError> {
+        let node = self.alternation()?;
+        if self.pos < self.chars.len() {
+            //the only way to stop early is an unmatched ')'
+            return self.error(format!("unmatched ) in {}", self.pattern));
+        }
+        Ok(node)
+    }
+
+    //records the size and height of the node just parsed, refusing the pattern when it gets too big
+    fn measure(&mut self, start: usize, size: usize, height: usize) -> Result<(), RegexError> {
+        self.size = size;
+        self.height = height;
+        if size > MAX_PROGRAM {
+            self.pos = start;
+            return self.error(format!("the pattern is too big, it compiles to over {} instructions", MAX_PROGRAM));
+        }
+        if height > MAX_DEPTH {
+            self.pos = start;
+            return self.error(format!("groups and repetitions nest more than {} deep", MAX_DEPTH));
+        }
+        Ok(())
+    }
+
+    fn alternation(&mut self) -> Result<Node, RegexError> {
+        let start = self.pos;
+        let mut branches = vec![self.concat()?];
+        let (mut size, mut height) = (self.size, self.height);
+        while self.eat('|') {
+            branches.push(self.concat()?);
+            //a split before and a jump after every branch but the last
+            size = size.saturating_add(self.size + 2);
+            height = height.max(self.height);
+        }
+        Ok(if branches.len() == 1 {
+            branches.pop().unwrap()
+        } else {
+            self.measure(start, size, height + 1)?;
+            Node::Alternate(branches)
+        })
+    }
+
+    fn concat(&mut self) -> Result<Node, RegexError> {
+        let start = self.pos;
+        let mut items = Vec::new();
+        let (mut size, mut height) = (0usize, 0);
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            items.push(self.repeat(atom)?);
+            size = size.saturating_add(self.size);
+            height = height.max(self.height);
+        }
+        Ok(match items.len() {
+            0 => {
+                self.measure(start, 0, 1)?;
+                Node::Empty
+            }
+            1 => items.pop().unwrap(),
+            _ => {
+                self.measure(start, size, height + 1)?;
+                Node::Concat(items)
+            }
+        })
+    }
+
+    fn repeat(&mut self, mut node: Node) -> Result<Node, RegexError> {
+        loop {
+            let start = self.pos;
+            let (min, max) = match self.peek() {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some('{') => match self.counts()? {
+                    Some(counts) => counts,
+                    None => return Ok(node),
+                },
+                _ => return Ok(node),
+            };
+            if self.pos == start {
+                self.pos += 1;
+            }
+            if let Node::Start | Node::End | Node::WordBoundary(_) | Node::Empty = node {
+                self.pos = start;
+                return self.error("nothing to repeat");
+            }
+            let greedy = !self.eat('?');
+            //min copies, then either a loop around one more or a split before each optional copy
+            let copies = match max {
+                None => (self.size + 2) as u64,
+                Some(max) => u64::from(max - min) * (self.size as u64 + 1),
+            };
+            let size = (u64::from(min) * self.size as u64 + copies).min(MAX_PROGRAM as u64 + 1);
+            self.measure(start, size as usize, self.height + 1)?;
+            node = Node::Repeat {
+                node: Box::new(node),
+                min,
+                max,
+                greedy,
+            };
+        }
+    }
+
+    //`{n}`, `{n,}` or `{n,m}`; anything else is a literal `{`, which is what grep does too
+    fn counts(&mut self) -> Result<Option<(u32, Option<u32>)>, RegexError> {
+        let close = match self.chars[self.pos..].iter().position(|&c| c == '}') {
+            Some(i) => self.pos + i,
+            None => return Ok(None),
+        };
+        let inside: String = self.chars[self.pos + 1..close].iter().collect();
+        //a count too big for a u32 is still a count, just one over the limit below
+        let number = |s: &str| {
+            let s = s.trim();
+            if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            Some(s.parse::<u32>().unwrap_or(u32::MAX))
+        };
+        let counts = match inside.find(',') {
+            None => number(&inside).map(|n| (n, Some(n))),
+            Some(i) if inside[i + 1..].trim().is_empty() => number(&inside[..i]).map(|n| (n, None)),
+            Some(i) => number(&inside[..i]).and_then(|n| number(&inside[i + 1..]).map(|m| (n, Some(m)))),
+        };
+        let (min, max) = match counts {
+            Some(counts) => counts,
+            None => return Ok(None),
+        };
+        if max.is_some_and(|max| max < min) {
+            return self.error(format!("{{{}}} has its bounds the wrong way round", inside));
+        }
+        if min > MAX_REPEAT || max.is_some_and(|max| max > MAX_REPEAT) {
+            return self.error(format!("repetition counts are limited to {}", MAX_REPEAT));
+        }
+        self.pos = close + 1;
+        Ok(Some((min, max)))
+    }
+
+    fn atom(&mut self) -> Result<Node, RegexError> {
+        let c = self.peek().unwrap();
+        self.pos += 1;
+        //one instruction, unless it's a group: then the inner node has its own size and height
+        self.size = 1;
+        self.height = 1;
+        match c {
+            '(' => {
+                //(?:...) is accepted for people used to it; we don't capture anyway
+                if self.peek() == Some('?') {
+                    if self.chars.get(self.pos + 1) == Some(&':') {
+                        self.pos += 2;
+                    } else {
+                        return self.error("only (?: groups are supported");
+                    }
+                }
+                let open = self.pos - 1;
+                if self.depth == MAX_DEPTH {
+                    self.pos = open;
+                    return self.error(format!("groups nest more than {} deep", MAX_DEPTH));
+                }
+                self.depth += 1;
+                let inner = self.alternation()?;
+                self.depth -= 1;
+                if !self.eat(')') {
+                    self.pos = open;
+                    return self.error("unclosed (");
+                }
+                Ok(inner)
+            }
+            '[' => self.class(),
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::Start),
+            '$' => Ok(Node::End),
+            '*' | '+' | '?' => {
+                self.pos -= 1;
+                self.error("nothing to repeat")
+            }
+            '\\' => match self.escape()? {
+                Escaped::Char(c) => Ok(Node::Char(c)),
+                Escaped::Perl(perl, negated) => Ok(Node::Class(Class {
+                    items: vec![ClassItem::Perl(perl, negated)],
+                    negated: false,
+                })),
+                Escaped::WordBoundary(on) => Ok(Node::WordBoundary(on)),
+            },
+            c => Ok(Node::Char(c)),
+        }
+    }
+
+    fn escape(&mut self) -> Result<Escaped, RegexError> {
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return self.error("pattern ends with a lone \\"),
+        };
+        self.pos += 1;
+        Ok(match c {
+            'd' => Escaped::Perl(Perl::Digit, false),
+            'D' => Escaped::Perl(Perl::Digit, true),
+            'w' => Escaped::Perl(Perl::Word, false),
+            'W' => Escaped::Perl(Perl::Word, true),
+            's' => Escaped::Perl(Perl::Space, false),
+            'S' => Escaped::Perl(Perl::Space, true),
+            'b' => Escaped::WordBoundary(true),
+            'B' => Escaped::WordBoundary(false),
+            'n' => Escaped::Char('\n'),
+            't' => Escaped::Char('\t'),
+            'r' => Escaped::Char('\r'),
+            c if c.is_alphanumeric() => {
+                self.pos -= 1;
+                return self.error(format!("unknown escape \\{}", c));
+            }
+            c => Escaped::Char(c),
+        })
+    }
+
+    fn class(&mut self) -> Result<Node, RegexError> {
+        let open = self.pos - 1;
+        let negated = self.eat('^');
+        let mut items = Vec::new();
+        let mut first = true;
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => {
+                    self.pos = open;
+                    return self.error("unclosed [");
+                }
+            };
+            self.pos += 1;
+            //a ] right after the [ (or [^) is a literal, so []] and [^]] work
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+
+            let low = match c {
+                '\\' => match self.escape()? {
+                    Escaped::Char(c) => c,
+                    Escaped::Perl(perl, negated) => {
+                        items.push(ClassItem::Perl(perl, negated));
+                        continue;
+                    }
+                    Escaped::WordBoundary(_) => return self.error("\\b makes no sense inside [ ]"),
+                },
+                c => c,
+            };
+            //a - at either end is a literal
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&c| c != ']') {
+                self.pos += 1;
+                let high = match self.peek().unwrap() {
+                    '\\' => {
+                        self.pos += 1;
+                        match self.escape()? {
+                            Escaped::Char(c) => c,
+                            _ => return self.error("a range must end in a single character"),
+                        }
+                    }
+                    c => {
+                        self.pos += 1;
+                        c
+                    }
+                };
+                if high < low {
+                    return self.error(format!("range {}-{} is out of order", low, high));
+                }
+                items.push(ClassItem::Range(low, high));
+            } else {
+                items.push(ClassItem::Range(low, low));
+            }
+        }
+        Ok(Node::Class(Class { items, negated }))
+    }
+}
+
+enum Escaped {
+    Char(char),
+    Perl(Perl, bool),
+    WordBoundary(bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    WordBoundary(bool),
+    //try the first target, then the second; the order is what makes a repetition greedy or lazy
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+struct Compiler {
+    program: Vec<Inst>,
+}
+
+impl Compiler {
+    fn emit(&mut self, inst: Inst) -> usize {
+        self.program.push(inst);
+        self.program.len() - 1
+    }
+
+    fn compile(&mut self, node: &Node) {
+        match node {
+            Node::Empty => {}
+            Node::Char(c) => {
+                self.emit(Inst::Char(*c));
+            }
+            Node::Any => {
+                self.emit(Inst::Any);
+            }
+            Node::Class(class) => {
+                self.emit(Inst::Class(class.clone()));
+            }
+            Node::Start => {
+                self.emit(Inst::Start);
+            }
+            Node::End => {
+                self.emit(Inst::End);
+            }
+            Node::WordBoundary(on) => {
+                self.emit(Inst::WordBoundary(*on));
+            }
+            Node::Concat(items) => {
+                for item in items {
+                    self.compile(item);
+                }
+            }
+            Node::Alternate(branches) => {
+                //split to each branch in turn, every branch then jumps past the rest
+                let mut jumps = Vec::new();
+                for (i, branch) in branches.iter().enumerate() {
+                    if i + 1 < branches.len() {
+                        let split = self.emit(Inst::Split(0, 0));
+                        self.compile(branch);
+                        jumps.push(self.emit(Inst::Jump(0)));
+                        let next = self.program.len();
+                        self.program[split] = Inst::Split(split + 1, next);
+                    } else {
+                        self.compile(branch);
+                    }
+                }
+                let end = self.program.len();
+                for jump in jumps {
+                    self.program[jump] = Inst::Jump(end);
+                }
+            }
+            Node::Repeat { node, min, max, greedy } => {
+                for _ in 0..*min {
+                    self.compile(node);
+                }
+                match max {
+                    //x* : L1: split L2, L3; L2: x; jump L1; L3:
+                    None => {
+                        let split = self.emit(Inst::Split(0, 0));
+                        self.compile(node);
+                        self.emit(Inst::Jump(split));
+                        let end = self.program.len();
+                        self.program[split] = self.split(split + 1, end, *greedy);
+                    }
+                    //x{0,n} : n optional copies, each skipping to the very end when not taken
+                    Some(max) => {
+                        let mut splits = Vec::new();
+                        for _ in *min..*max {
+                            splits.push(self.emit(Inst::Split(0, 0)));
+                            self.compile(node);
+                        }
+                        let end = self.program.len();
+                        for split in splits {
+                            self.program[split] = self.split(split + 1, end, *greedy);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn split(&self, take: usize, skip: usize, greedy: bool) -> Inst {
+        if greedy {
+            Inst::Split(take, skip)
+        } else {
+            Inst::Split(skip, take)
+        }
+    }
+}
+
+/// A compiled pattern, matched against one line at a time.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    program: Vec<Inst>,
+    case_insensitive: bool,
+    whole_word: bool,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, RegexError> {
+        let parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            pattern,
+            depth: 0,
+            size: 0,
+            height: 0,
+        };
+        let node = parser.parse()?;
+        let mut compiler = Compiler { program: Vec::new() };
+        compiler.compile(&node);
+        compiler.emit(Inst::Match);
+        Ok(Regex {
+            program: compiler.program,
+            case_insensitive: false,
+            whole_word: false,
+        })
+    }
+
+    pub fn case_insensitive(mut self, on: bool) -> Regex {
+        self.case_insensitive = on;
+        self
+    }
+
+    /// Only match with no word character right before or right after the match, like grep -w.
+    /// Unlike wrapping the pattern in `\b`, this allows matches that begin or end with
+    /// punctuation, and every way the pattern can match at a start is tried, not just the first.
+    pub fn whole_word(mut self, on: bool) -> Regex {
+        self.whole_word = on;
+        self
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.find(text).is_some()
+    }
+
+    /// The leftmost match as a byte range; among matches starting there, the one the
+    /// pattern prefers (longest for greedy repetition, first branch of an alternation).
+    pub fn find(&self, text: &str) -> Option<(usize, usize)> {
+        self.find_at(text, 0)
+    }
+
+    /// Like `find`, but only for matches starting at byte `start` or later. `^` and `\b`
+    /// still look at the whole text.
+    pub fn find_at(&self, text: &str, start: usize) -> Option<(usize, usize)> {
+        let mut current: Vec<(usize, usize)> = Vec::new();
+        let mut next: Vec<(usize, usize)> = Vec::new();
+        let mut visit = Visit {
+            seen: vec![false; self.program.len()],
+            stack: Vec::new(),
+        };
+        let mut matched = None;
+
+        let mut pos = start;
+        if self.may_start(text, pos) {
+            self.add(&mut current, &mut visit, 0, pos, text, pos);
+        }
+        loop {
+            let c = text[pos..].chars().next();
+            for seen in visit.seen.iter_mut() {
+                *seen = false;
+            }
+            next.clear();
+
+            for &(pc, thread_start) in &current {
+                let step = match (&self.program[pc], c) {
+                    (Inst::Match, _) if self.whole_word && c.is_some_and(is_word) => false,
+                    (Inst::Match, _) => {
+                        matched = Some((thread_start, pos));
+                        //every thread after this one has a lower priority, drop them
+                        break;
+                    }
+                    (Inst::Char(expected), Some(c)) => self.same(*expected, c),
+                    (Inst::Any, Some(_)) => true,
+                    (Inst::Class(class), Some(c)) => self.in_class(class, c),
+                    _ => false,
+                };
+                if step {
+                    let after = pos + c.unwrap().len_utf8();
+                    self.add(&mut next, &mut visit, pc + 1, thread_start, text, after);
+                }
+            }
+
+            let c = match c {
+                Some(c) => c,
+                None => break,
+            };
+            pos += c.len_utf8();
+            //no match yet: also try one starting here, after everything already running
+            if matched.is_none() && self.may_start(text, pos) {
+                self.add(&mut next, &mut visit, 0, pos, text, pos);
+            }
+            std::mem::swap(&mut current, &mut next);
+            //with a match in hand and nothing left that could beat it, we are done
+            if current.is_empty() && matched.is_some() {
+                break;
+            }
+        }
+        matched
+    }
+
+    fn may_start(&self, text: &str, pos: usize) -> bool {
+        !self.whole_word || !text[..pos].chars().next_back().is_some_and(is_word)
+    }
+
+    //follows jumps, splits and assertions from `pc` so the list only holds instructions that
+    //consume a character (or Match); `seen` keeps a thread from being added twice per step.
+    //a chain of splits can be as long as the program, so this keeps its own stack instead of
+    //recursing; the first target of a split is pushed last, to be followed first
+    fn add(&self, list: &mut Vec<(usize, usize)>, visit: &mut Visit, pc: usize, start: usize, text: &str, pos: usize) {
+        visit.stack.push(pc);
+        while let Some(pc) = visit.stack.pop() {
+            if visit.seen[pc] {
+                continue;
+            }
+            visit.seen[pc] = true;
+            match self.program[pc] {
+                Inst::Jump(to) => visit.stack.push(to),
+                Inst::Split(first, second) => {
+                    visit.stack.push(second);
+                    visit.stack.push(first);
+                }
+                Inst::Start => {
+                    if pos == 0 {
+                        visit.stack.push(pc + 1);
+                    }
+                }
+                Inst::End => {
+                    if pos == text.len() {
+                        visit.stack.push(pc + 1);
+                    }
+                }
+                Inst::WordBoundary(on) => {
+                    let before = text[..pos].chars().next_back().is_some_and(is_word);
+                    let after = text[pos..].chars().next().is_some_and(is_word);
+                    if (before != after) == on {
+                        visit.stack.push(pc + 1);
+                    }
+                }
+                _ => list.push((pc, start)),
+            }
+        }
+    }
+
+    fn same(&self, expected: char, c: char) -> bool {
+        expected == c || (self.case_insensitive && fold(expected) == fold(c))
+    }
+
+    fn in_class(&self, class: &Class, c: char) -> bool {
+        if !self.case_insensitive {
+            return class.matches(c);
+        }
+        //[a-z] should take `Q` too: try the character as it is, folded and in uppercase
+        let lower = fold(c);
+        let mut upper = c.to_uppercase();
+        let upper = match (upper.next(), upper.next()) {
+            (Some(upper), None) => upper,
+            _ => c,
+        };
+        if class.negated {
+            class.matches(c) && class.matches(lower) && class.matches(upper)
+        } else {
+            class.matches(c) || class.matches(lower) || class.matches(upper)
+        }
+    }
+}
+
+//what Regex::add needs to walk the program from one thread, kept across calls to save allocations
+struct Visit {
+    seen: Vec<bool>,
+    stack: Vec<usize>,
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(pattern: &str, text: &str) -> Option<(usize, usize)> {
+        Regex::new(pattern).unwrap().find(text)
+    }
+
+    #[test]
+    fn literals_and_classes() {
+        assert_eq!(Some((15, 19)), find("duct", "safe, fast, productive."));
+        assert_eq!(Some((0, 3)), find("[a-c]+", "abcd"));
+        assert_eq!(Some((3, 4)), find("[^a-c]", "abcd"));
+        assert_eq!(Some((4, 7)), find(r"\d+", "abc 123 x"));
+        assert_eq!(Some((0, 3)), find(r"\w\W\s", "a. b"));
+        assert_eq!(Some((1, 2)), find("[]x]", "a]"));
+        assert_eq!(Some((1, 2)), find("[a-]", "b-"));
+        assert_eq!(Some((1, 3)), find(r"a\.", "xa.b"));
+        assert_eq!(None, find("a.c", "ac"));
+    }
+
+    #[test]
+    fn anchors_and_word_boundaries() {
+        assert!(Regex::new("^Rust").unwrap().is_match("Rust:"));
+        assert!(!Regex::new("^Rust").unwrap().is_match("Trust"));
+        assert!(Regex::new("three.$").unwrap().is_match("Pick three."));
+        assert_eq!(Some((5, 8)), find(r"\bcat\b", "scat cat"));
+        assert_eq!(Some((1, 4)), find(r"\Bcat", "scat cat"));
+        assert_eq!(Some((0, 0)), find("", "anything"));
+    }
+
+    #[test]
+    fn alternation_groups_and_repetition() {
+        assert_eq!(Some((0, 4)), find("(ab)+", "ababa"));
+        assert_eq!(Some((4, 7)), find("cat|dog", "hot dog"));
+        //the first alternative that matches wins, like Perl
+        assert_eq!(Some((0, 1)), find("a|ab", "ab"));
+        assert_eq!(Some((0, 2)), find("(?:a|b)c?", "ac"));
+        assert_eq!(Some((0, 3)), find("a{2,3}", "aaaa"));
+        assert_eq!(Some((0, 2)), find("a{2}", "aaaa"));
+        assert_eq!(Some((0, 4)), find("a{2,}", "aaaa"));
+        assert_eq!(Some((0, 1)), find("a+?", "aaaa"));
+        assert_eq!(Some((0, 6)), find("<.*>", "<a><b> x"));
+        assert_eq!(Some((0, 3)), find("<.*?>", "<a><b> x"));
+        assert_eq!(Some((2, 5)), find("x{1}y{", "a xy{"));
+        assert_eq!(Some((0, 6)), find("colou?r ", "color me"));
+    }
+
+    #[test]
+    fn no_exponential_blowup() {
+        //this takes a backtracking engine ages: (a*)* against a's that fail at the end
+        let text = "a".repeat(5000) + "!";
+        assert!(!Regex::new("^(a*)*$").unwrap().is_match(&text));
+        assert!(Regex::new("(a|aa)+!").unwrap().is_match(&text));
+    }
+
+    #[test]
+    fn limits_size_and_nesting() {
+        //each repetition is fine on its own, together they'd be a billion instructions
+        let err = Regex::new("((a{1000}){1000}){1000}").unwrap_err();
+        assert_eq!(10, err.position);
+        assert!(Regex::new("(a{1000}){99}").is_ok());
+        assert!(Regex::new("x|(a{1000}){10}|(b{1000}){90}").is_err());
+
+        let deep = "(".repeat(100_000) + &")".repeat(100_000);
+        assert_eq!(MAX_DEPTH, Regex::new(&deep).unwrap_err().position);
+        assert!(Regex::new(&("a".to_string() + &"?".repeat(100_000))).is_err());
+        let nested = "(".repeat(MAX_DEPTH - 1) + "a" + &")".repeat(MAX_DEPTH - 1);
+        assert!(Regex::new(&nested).unwrap().is_match("a"));
+
+        //a long chain of optional parts is followed without recursing
+        let chain = Regex::new("(?:(?:a?){1000}){40}b").unwrap();
+        assert_eq!(Some((0, 2)), chain.find("ab"));
+    }
+
+    #[test]
+    fn case_insensitive_matching() {
+        let regex = Regex::new("pro[d-e]uct").unwrap().case_insensitive(true);
+        assert!(regex.is_match("Safe, faSt, proDuctive."));
+        assert!(!Regex::new("[^a-z]").unwrap().case_insensitive(true).is_match("AbC"));
+    }
+
+    #[test]
+    fn unicode_text() {
+        assert_eq!(Some((0, 6)), find("é+", "ééé"));
+        assert_eq!(Some((5, 8)), find(r"\w+", "¿¿ añ"));
+    }
+
+    #[test]
+    fn reports_bad_patterns() {
+        assert_eq!(2, Regex::new("ab(").unwrap_err().position);
+        assert!(Regex::new("(ab").is_err());
+        assert!(Regex::new("ab)").is_err());
+        assert!(Regex::new("[ab").is_err());
+        assert!(Regex::new("*a").is_err());
+        assert!(Regex::new(r"\q").is_err());
+        assert!(Regex::new("a{3,1}").is_err());
+        assert!(Regex::new("a\\").is_err());
+        assert!(Regex::new("(?=x)").is_err());
+        assert!(Regex::new("[z-a]").is_err());
+        assert!(Regex::new("a{2000}").is_err());
+        assert!(Regex::new("a{99999999999}").is_err());
+        assert!(Regex::new("a{1,99999999999}").is_err());
+        assert!(Regex::new("^*").is_err());
+        assert_eq!("invalid pattern at position 0: nothing to repeat", Regex::new("+").unwrap_err().to_string());
+    }
+}
@@ -0,0 +1,223 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+
+use crate::Matcher;
+
+/// Why a line is reported: it was selected, or it is context around a selected line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineKind {
+    Match,
+    Context,
+}
+
+/// A line reported by a search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    /// Counting from 1.
+    pub number: usize,
+    /// Where the line starts, in bytes from the start of the input.
+    pub offset: u64,
+    pub kind: LineKind,
+    /// Without the line ending; bytes that aren't UTF-8 are replaced with U+FFFD.
+    pub text: String,
+    /// The line as it was read, only kept when `text` had bytes replaced.
+    pub bytes: Option<Vec<u8>>,
+}
+
+impl Line {
+    /// Where byte `at` of `text` is in the line as it was read. The two only differ after a
+    /// replaced run of bytes, which takes 3 bytes in `text` whatever its length was.
+    pub fn raw_offset(&self, at: usize) -> usize {
+        let bytes = match &self.bytes {
+            Some(bytes) => bytes,
+            None => return at,
+        };
+        let (mut text_pos, mut raw_pos) = (0, 0);
+        for chunk in bytes.utf8_chunks() {
+            let valid = chunk.valid().len();
+            if at <= text_pos + valid {
+                return raw_pos + (at - text_pos);
+            }
+            text_pos += valid;
+            raw_pos += valid;
+            if !chunk.invalid().is_empty() {
+                text_pos += char::REPLACEMENT_CHARACTER.len_utf8();
+                raw_pos += chunk.invalid().len();
+            }
+        }
+        raw_pos
+    }
+}
+
+/// Searches input line by line and reports the selected lines, with as many lines of context
+/// around them as asked for. Windows that overlap or touch are merged, so every line is
+/// reported at most once and in order; a jump in line numbers is where grep prints `--`.
+pub struct Searcher<'m> {
+    matcher: &'m Matcher,
+    invert: bool,
+    before: usize,
+    after: usize,
+}
+
+impl<'m> Searcher<'m> {
+    pub fn new(matcher: &'m Matcher) -> Searcher<'m> {
+        Searcher {
+            matcher,
+            invert: false,
+            before: 0,
+            after: 0,
+        }
+    }
+
+    /// Selects the lines that don't match instead.
+    pub fn invert(mut self, invert: bool) -> Searcher<'m> {
+        self.invert = invert;
+        self
+    }
+
+    pub fn context(mut self, before: usize, after: usize) -> Searcher<'m> {
+        self.before = before;
+        self.after = after;
+        self
+    }
+
+    /// Reads `reader` to the end, calling `found` for every line to report. Only the current
+    /// line and the `before` context are held in memory. `found` returns false to stop early.
+    pub fn search<R: BufRead>(&self, mut reader: R, mut found: impl FnMut(&Line) -> io::Result<bool>) -> io::Result<()> {
+        let mut buffer = Vec::new();
+        //context lines that will be reported if a match comes along soon enough
+        let mut before: VecDeque<Line> = VecDeque::with_capacity(self.before);
+        let mut after_left = 0;
+        let mut number = 0;
+        let mut offset = 0;
+        loop {
+            buffer.clear();
+            let read = reader.read_until(b'\n', &mut buffer)?;
+            if read == 0 {
+                return Ok(());
+            }
+            number += 1;
+            let start = offset;
+            offset += read as u64;
+            //the same line endings as str::lines: \n or \r\n
+            if buffer.last() == Some(&b'\n') {
+                buffer.pop();
+                if buffer.last() == Some(&b'\r') {
+                    buffer.pop();
+                }
+            }
+            //borrows when the line is valid UTF-8, which is nearly always
+            let text = String::from_utf8_lossy(&buffer);
+            let line = |kind| Line {
+                number,
+                offset: start,
+                kind,
+                text: text.to_string(),
+                bytes: match text {
+                    Cow::Borrowed(_) => None,
+                    Cow::Owned(_) => Some(buffer.clone()),
+                },
+            };
+
+            if self.matcher.is_match(&text) != self.invert {
+                for context in before.drain(..) {
+                    if !found(&context)? {
+                        return Ok(());
+                    }
+                }
+                if !found(&line(LineKind::Match))? {
+                    return Ok(());
+                }
+                after_left = self.after;
+            } else if after_left > 0 {
+                after_left -= 1;
+                if !found(&line(LineKind::Context))? {
+                    return Ok(());
+                }
+            } else if self.before > 0 {
+                if before.len() == self.before {
+                    before.pop_front();
+                }
+                before.push_back(line(LineKind::Context));
+            }
+        }
+    }
+
+    /// Every line to report from `contents`, for when the input is in memory already.
+    pub fn lines(&self, contents: &str) -> Vec<Line> {
+        let mut lines = Vec::new();
+        self.search(contents.as_bytes(), |line| {
+            lines.push(line.clone());
+            Ok(true)
+        })
+        .expect("reading from memory doesn't fail");
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    fn matcher(query: &str) -> Matcher {
+        let config = Config::new(vec!["minigrep", "-s", query].into_iter().map(String::from)).unwrap();
+        Matcher::new(&config).unwrap()
+    }
+
+    fn numbers(lines: &[Line]) -> Vec<(usize, LineKind)> {
+        lines.iter().map(|line| (line.number, line.kind)).collect()
+    }
+
+    #[test]
+    fn reads_lossily_with_offsets() {
+        let matcher = matcher("fast");
+        //a Latin-1 é and Windows line endings
+        let contents = &b"Rust:\r\nsafe, fast, productive.\r\nfast caf\xe9\nPick three."[..];
+
+        let mut lines = Vec::new();
+        Searcher::new(&matcher)
+            .search(contents, |line| {
+                lines.push(line.clone());
+                Ok(true)
+            })
+            .unwrap();
+        assert_eq!(2, lines.len());
+        assert_eq!((2, 7, "safe, fast, productive."), (lines[0].number, lines[0].offset, lines[0].text.as_str()));
+        assert_eq!((3, 32, "fast caf\u{fffd}"), (lines[1].number, lines[1].offset, lines[1].text.as_str()));
+        assert_eq!((None, Some(&b"fast caf\xe9"[..])), (lines[0].bytes.as_deref(), lines[1].bytes.as_deref()));
+        //past the replaced byte, text is two bytes ahead of the line as read
+        assert_eq!((5, 8, 9), (lines[1].raw_offset(5), lines[1].raw_offset(8), lines[1].raw_offset(11)));
+
+        let mut inverted = Vec::new();
+        Searcher::new(&matcher)
+            .invert(true)
+            .search(contents, |line| {
+                inverted.push(line.number);
+                Ok(false)
+            })
+            .unwrap();
+        assert_eq!(vec![1], inverted);
+    }
+
+    #[test]
+    fn context_windows() {
+        let matcher = matcher("x");
+        let contents = "a\nx\nb\nc\nd\ne\nx\nf\nx\ng\nh\n";
+        use LineKind::{Context, Match};
+
+        let lines = Searcher::new(&matcher).context(1, 1).lines(contents);
+        assert_eq!(
+            vec![(1, Context), (2, Match), (3, Context), (6, Context), (7, Match), (8, Context), (9, Match), (10, Context)],
+            numbers(&lines)
+        );
+
+        //windows that touch are merged: 2+2 after the first x reaches the 2 before the second
+        let lines = Searcher::new(&matcher).context(2, 2).lines(contents);
+        assert_eq!((1..=11).collect::<Vec<_>>(), lines.iter().map(|line| line.number).collect::<Vec<_>>());
+
+        let lines = Searcher::new(&matcher).context(0, 0).lines(contents);
+        assert_eq!(vec![(2, Match), (7, Match), (9, Match)], numbers(&lines));
+    }
+}
@@ -0,0 +1,163 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::ignore::Ignore;
+
+//how much of a file is looked at to decide whether it is binary, the same amount as git
+const BINARY_CHECK: usize = 8000;
+
+/// Turns the paths from the command line into the files to search, in order.
+///
+/// Files, and `-` for standard input, are taken as they are. Directories are only allowed with `recursive`; their files
+/// are listed in name order, skipping `.git` and, with `use_ignore`, whatever the
+/// .gitignore and .ignore files inside say. A path that can't be read gives an error in its
+/// place, the rest are still listed.
+///
+/// The tree is walked as the files are asked for, one directory read at a time, so searching
+/// starts right away and a huge tree is never held in memory whole.
+pub fn files(paths: &[PathBuf], recursive: bool, use_ignore: bool) -> Files<'_> {
+    Files {
+        paths: paths.iter(),
+        recursive,
+        use_ignore,
+        dirs: Vec::new(),
+    }
+}
+
+/// The iterator `files` returns.
+pub struct Files<'a> {
+    paths: std::slice::Iter<'a, PathBuf>,
+    recursive: bool,
+    use_ignore: bool,
+    //the directories being listed, innermost last, with what is left of each
+    dirs: Vec<(std::vec::IntoIter<fs::DirEntry>, Ignore)>,
+}
+
+impl Files<'_> {
+    //starts listing `dir`, whose files are filtered by `ignore`
+    fn enter(&mut self, dir: &Path, ignore: Ignore) -> io::Result<()> {
+        let mut entries = fs::read_dir(dir)
+            .and_then(|entries| entries.collect::<io::Result<Vec<_>>>())
+            .map_err(|err| with_path(dir, err))?;
+        entries.sort_by_key(|entry| entry.file_name());
+        self.dirs.push((entries.into_iter(), ignore));
+        Ok(())
+    }
+
+    //the next file in the directories being listed, None once they are all done
+    fn next_in_dirs(&mut self) -> Option<io::Result<PathBuf>> {
+        while let Some((entries, ignore)) = self.dirs.last_mut() {
+            let entry = match entries.next() {
+                Some(entry) => entry,
+                None => {
+                    self.dirs.pop();
+                    continue;
+                }
+            };
+            let path = entry.path();
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(err) => return Some(Err(with_path(&path, err))),
+            };
+            //like grep -r, symbolic links are only followed when named on the command line
+            if file_type.is_symlink() || ignore.is_ignored(&path, file_type.is_dir()) {
+                continue;
+            }
+            if !file_type.is_dir() {
+                return Some(Ok(path));
+            }
+            if entry.file_name() != ".git" {
+                let ignore = if self.use_ignore { ignore.child(&path) } else { ignore.clone() };
+                if let Err(err) = self.enter(&path, ignore) {
+                    return Some(Err(err));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl Iterator for Files<'_> {
+    type Item = io::Result<PathBuf>;
+
+    fn next(&mut self) -> Option<io::Result<PathBuf>> {
+        loop {
+            if let Some(file) = self.next_in_dirs() {
+                return Some(file);
+            }
+            let path = self.paths.next()?.clone();
+            if path == Path::new("-") {
+                return Some(Ok(path));
+            }
+            match fs::metadata(&path) {
+                Err(err) => return Some(Err(with_path(&path, err))),
+                Ok(metadata) if metadata.is_dir() => {
+                    if !self.recursive {
+                        let err = io::Error::other("Is a directory, use -r to search it");
+                        return Some(Err(with_path(&path, err)));
+                    }
+                    let ignore = if self.use_ignore { Ignore::default().child(&path) } else { Ignore::default() };
+                    if let Err(err) = self.enter(&path, ignore) {
+                        return Some(Err(err));
+                    }
+                }
+                Ok(_) => return Some(Ok(path)),
+            }
+        }
+    }
+}
+
+/// A NUL byte near the start is a good sign of a binary file; text never has one.
+pub fn is_binary(contents: &[u8]) -> bool {
+    contents[..contents.len().min(BINARY_CHECK)].contains(&0)
+}
+
+fn with_path(path: &Path, err: io::Error) -> io::Error {
+    io::Error::new(err.kind(), format!("{}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walks_directories_in_order() {
+        let dir = std::env::temp_dir().join(format!("minigrep-walk-{}", std::process::id()));
+        for sub in &["src/nested", "target/debug", ".git"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        for file in &["b.txt", "a.txt", "src/lib.rs", "src/nested/deep.rs", "target/debug/out", ".git/HEAD", "x.log"] {
+            fs::write(dir.join(file), "text").unwrap();
+        }
+        fs::write(dir.join(".gitignore"), "target/\n*.log\n").unwrap();
+
+        let listed = |use_ignore| -> Vec<String> {
+            files(std::slice::from_ref(&dir), true, use_ignore)
+                .map(|file| file.unwrap().strip_prefix(&dir).unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+        assert_eq!(vec![".gitignore", "a.txt", "b.txt", "src/lib.rs", "src/nested/deep.rs"], listed(true));
+        assert_eq!(
+            vec![".gitignore", "a.txt", "b.txt", "src/lib.rs", "src/nested/deep.rs", "target/debug/out", "x.log"],
+            listed(false)
+        );
+
+        //without -r a directory is an error, but the other paths are still there
+        let listed: Vec<_> = files(&[dir.clone(), dir.join("a.txt"), dir.join("missing")], false, true).collect();
+        assert!(listed[0].is_err());
+        assert_eq!(dir.join("a.txt"), *listed[1].as_ref().unwrap());
+        assert_eq!(io::ErrorKind::NotFound, listed[2].as_ref().unwrap_err().kind());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn spots_binary_files() {
+        assert!(!is_binary(b"plain text\n"));
+        assert!(is_binary(b"\x7fELF\x02\x01\x00\x00"));
+        let mut late = vec![b'a'; BINARY_CHECK];
+        late.push(0);
+        assert!(!is_binary(&late));
+    }
+}
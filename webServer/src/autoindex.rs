@@ -0,0 +1,142 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::date::http_date;
+use crate::error::HttpError;
+use crate::request::Request;
+use crate::response::Response;
+use crate::url::encode_path;
+
+#[derive(Debug, Serialize)]
+struct Entry {
+    name: String,
+    dir: bool,
+    size: u64,
+    //seconds since the epoch in JSON; the HTML page shows an HTTP date
+    modified: Option<u64>,
+}
+
+/// A listing of `dir`, which is served at `url_path` (ending in `/`): HTML, or JSON for
+/// clients whose `Accept` asks for it. Directories come first, then files, each by name;
+/// hidden entries are left out.
+pub(crate) fn listing(dir: &Path, url_path: &str, request: &Request) -> Result<Response, HttpError> {
+    let entries = read_entries(dir)?;
+    let wants_json = request
+        .header("Accept")
+        .is_some_and(|accept| accept.contains("application/json") && !accept.contains("text/html"));
+
+    let response = if wants_json {
+        Response::json(200, &entries)?
+    } else {
+        Response::html(200, render(url_path, &entries))
+    };
+    Ok(response.with_header("Vary", "Accept"))
+}
+
+fn read_entries(dir: &Path) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        //an entry can vanish between read_dir and metadata, it's simply not listed then
+        let metadata = match fs::metadata(entry.path()) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        entries.push(Entry {
+            name,
+            dir: metadata.is_dir(),
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs()),
+        });
+    }
+    entries.sort_by(|a, b| b.dir.cmp(&a.dir).then_with(|| a.name.cmp(&b.name)));
+    Ok(entries)
+}
+
+fn render(url_path: &str, entries: &[Entry]) -> String {
+    let title = escape(&format!("Index of {}", url_path));
+    let mut page = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n</head>\n<body>\n\
+<h1>{0}</h1>\n<table>\n<tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n",
+        title
+    );
+    if url_path != "/" {
+        page.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+    for entry in entries {
+        let slash = if entry.dir { "/" } else { "" };
+        let modified = entry
+            .modified
+            .map(|secs| http_date(UNIX_EPOCH + Duration::from_secs(secs)))
+            .unwrap_or_default();
+        page.push_str(&format!(
+            "<tr><td><a href=\"{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            escape(&(encode_path(&entry.name) + slash)),
+            escape(&entry.name),
+            slash,
+            if entry.dir { String::from("-") } else { entry.size.to_string() },
+            modified
+        ));
+    }
+    page.push_str("</table>\n</body>\n</html>\n");
+    page
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_sorted_and_escaped() {
+        let dir = std::env::temp_dir().join(format!("webserver-autoindex-{}", std::process::id()));
+        fs::create_dir_all(dir.join("zeta")).unwrap();
+        fs::write(dir.join("<b>&.txt"), "12345").unwrap();
+        fs::write(dir.join("alpha.txt"), "").unwrap();
+        fs::write(dir.join(".hidden"), "").unwrap();
+
+        let html = listing(&dir, "/files/", &Request::new("GET", "/files/")).unwrap();
+        let html = String::from_utf8(html.body.as_bytes().to_vec()).unwrap();
+        assert!(html.contains("<title>Index of /files/</title>"));
+        assert!(html.contains("<a href=\"%3Cb%3E%26.txt\">&lt;b&gt;&amp;.txt</a></td><td>5</td>"));
+        assert!(!html.contains("hidden"));
+        let (zeta, lt, alpha) = (html.find("zeta/").unwrap(), html.find("&lt;b&gt;").unwrap(), html.find("alpha").unwrap());
+        assert!(zeta < lt && lt < alpha);
+
+        let mut request = Request::new("GET", "/files/");
+        request.headers.append("Accept", "application/json");
+        let json = listing(&dir, "/files/", &request).unwrap();
+        assert_eq!(Some("application/json"), json.headers.get("Content-Type"));
+        let entries: serde_json::Value = serde_json::from_slice(json.body.as_bytes()).unwrap();
+        assert_eq!("zeta", entries[0]["name"]);
+        assert_eq!(true, entries[0]["dir"]);
+        assert_eq!(5, entries[1]["size"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
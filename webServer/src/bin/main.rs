@@ -1,77 +1,154 @@
-use std::io::prelude::*;
-use std::net::TcpStream;
-use std::net::TcpListener;
-use std::fs;
-//use threadpool::ThreadPool;
-use webServer::ThreadPool;
-//use std::sync::mpsc::channel;
-
-//type alias, for conveince
-type Job = Box<dyn FnOnce() + Send + 'static>;
+use std::env;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use webServer::cgi::Cgi;
+use webServer::proxy::ReverseProxy;
+use webServer::reload::Watcher;
+use webServer::{Config, ErrorPages, FileCache, ProxyRoute, RateLimit, RateLimiter, Request, Response, Router, Server, Site, StaticFiles};
 
 fn main() {
-    //the stream consists of calling unwrap to terminate our program if the stream has any errors; if there aren’t any errors, the program prints a message.
-    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
-
-    let multi_threading = true;
-    let pool = ThreadPool::new(4);
+    //settings come from the file named on the command line, or server.conf next to us if there is one
+    let config_path = env::args().nth(1).unwrap_or_else(|| String::from("server.conf"));
+    let config = if Path::new(&config_path).exists() {
+        Config::load(Path::new(&config_path)).unwrap_or_else(|err| {
+            eprintln!("Problem loading {}: {}", config_path, err);
+            process::exit(1);
+        })
+    } else {
+        Config::default()
+    };
 
+    let mut kept = Kept::default();
+    let router = build_router(&config, &mut kept);
+    //a port in use or an unreadable certificate ends the program here, with a message instead of a panic
+    let server = Server::bind(&config, router).unwrap_or_else(|err| {
+        eprintln!("Problem starting the server: {}", err);
+        process::exit(1);
+    });
+    println!("Listening on http://{}", config.address);
+    if let Some(tls) = &config.tls {
+        println!("Listening on https://{}", tls.address);
+    }
 
-    //The reason we might receive errors from the incoming method when a client connects to the server is that we’re not actually iterating over connections. Instead, we’re iterating over connection attempts.
-    for stream in listener.incoming().take(2) {
-        let stream = stream.unwrap();
-        println!("Conection established");
-        pool.execute(|| {
-            handle_connection(stream);
+    //edits to the config take effect without a restart: we poll the file and swap in a router
+    //built from the new settings. Pages need no watching, the file cache checks them as they
+    //are served; what stays the same keeps its cache, rate limit counts and upstream health
+    let routes = server.routes();
+    if let Some(path) = Some(PathBuf::from(&config_path)).filter(|p| p.exists()) {
+        thread::spawn(move || {
+            let mut config = config;
+            let mut watcher = Watcher::new(&[&path]);
+            loop {
+                watcher.wait(Duration::from_secs(1));
+
+                let reloaded = match Config::load(&path) {
+                    Ok(reloaded) => reloaded,
+                    Err(err) => {
+                        eprintln!("Keeping the old settings, problem reloading {}: {}", path.display(), err);
+                        continue;
+                    }
+                };
+                if reloaded == config {
+                    continue;
+                }
+                config = reloaded;
+                routes.store(build_router(&config, &mut kept));
+                println!("Reloaded {} site(s)", config.sites().count());
+            }
         });
     }
 
+    server.run();
 }
 
-fn handle_connection(mut stream : TcpStream) {
-    //buffer 512 bytes in size
-    let mut buffer = [0; 512];
-    stream.read(&mut buffer).unwrap();
-    //println!("Request: {}", String::from_utf8_lossy(&buffer[..]));
+//the parts of a router that hold state, by the settings they were made from, so a reload can
+//hand them on to the next router when those settings didn't change
+#[derive(Default)]
+struct Kept {
+    //root and capacity
+    pages: Keyed<(PathBuf, usize), FileCache>,
+    limiters: Keyed<LimiterKey, RateLimiter>,
+    proxies: Keyed<(Vec<String>, ProxyRoute), ReverseProxy>,
+}
 
-    let get = b"GET / HTTP/1.1\r\n";
+type Keyed<K, V> = Vec<(K, Arc<V>)>;
 
-    // if buffer.starts_with(get) {
-    //
-    //     //return contents of the web server
-    //     let contents = fs::read_to_string("first.html").unwrap();
-    //
-    //     let response = format!("HTTP/1.1 200 OK\r\n\r\n{}", contents);
-    //     //Because the write operation could fail, we use unwrap on any error result as before. Again, in a real application you would add error handling here.
-    //     stream.write(response.as_bytes()).unwrap();
-    //     //flush will wait and prevent the program from continuing until all the bytes are written to the connection
-    //     stream.flush().unwrap();
-    //
-    // } else {
-    //     let status_line = "HTTP/1.1 404 NOT FOUND\r\n\r\n";
-    //     let contents = fs::read_to_string("404.html").unwrap();
-    //
-    //     let response = format!("{}{}", status_line, contents);
-    //
-    //     stream.write(response.as_bytes()).unwrap();
-    //     stream.flush().unwrap();
-    // }
+#[derive(PartialEq)]
+struct LimiterKey {
+    names: Vec<String>,
+    limit: RateLimit,
+    key_header: Option<String>,
+    trusted: Vec<IpAddr>,
+}
 
-    let (status_line, filename) = if buffer.starts_with(get) {
-        ("HTTP/1.1 200 OK\r\n\r\n", "first.html")
-    } else {
-        ("HTTP/1.1 404 NOT FOUND\r\n\r\n", "404.html")
+//the value kept under `key` in `old`, or a new one from `make`; either way it is kept in `new`
+fn reuse<K: PartialEq, V>(old: &[(K, Arc<V>)], new: &mut Keyed<K, V>, key: K, make: impl FnOnce() -> V) -> Arc<V> {
+    let value = match old.iter().find(|(k, _)| *k == key) {
+        Some((_, value)) => Arc::clone(value),
+        None => Arc::new(make()),
     };
+    new.push((key, Arc::clone(&value)));
+    value
+}
 
-    let contents = fs::read_to_string(filename).unwrap();
-
-    let response = format!("{}{}", status_line, contents);
-
-    stream.write(response.as_bytes()).unwrap();
-    stream.flush().unwrap();
-
-
+//addresses, threads and TLS only apply at startup; everything decided here can change on reload
+fn build_router(config: &Config, kept: &mut Kept) -> Router {
+    let old = std::mem::take(kept);
+    let mut router = site_router(&config.site, config, &old, kept);
+    if let Some(path) = &config.metrics_path {
+        let pages = Arc::clone(&kept.pages[0].1);
+        router = router.get(path, move |_: &Request| Ok(Response::text(200, pages.stats().to_string())));
+    }
+    for site in &config.hosts {
+        router = router.host(&site.names, site_router(site, config, &old, kept));
+    }
+    router
+}
 
+fn site_router(site: &Site, config: &Config, old: &Kept, kept: &mut Kept) -> Router {
+    let key = (site.root.clone(), config.cache_size);
+    let pages = reuse(&old.pages, &mut kept.pages, key, || {
+        FileCache::new(&site.root).with_capacity(config.cache_size)
+    });
+    //anything that isn't routed is looked up under the root
+    let files = StaticFiles::new(Arc::clone(&pages)).index(site.index.as_str()).autoindex(site.autoindex);
+    let mut errors = ErrorPages::new(pages).mode(config.mode);
+    for (status, page) in &site.error_pages {
+        errors = errors.page(status, page);
+    }
+    let mut router = Router::new().fallback(files).errors(errors);
+    for (prefix, scripts) in &site.cgi {
+        router = router.mount(prefix, Cgi::new(prefix, scripts).timeout(config.cgi_timeout));
+    }
+    for route in &site.proxies {
+        let key = (site.names.clone(), route.clone());
+        let proxy = reuse(&old.proxies, &mut kept.proxies, key, || ReverseProxy::new(&route.upstreams));
+        router = router.mount(&route.prefix, proxy);
+    }
+    for limit in &site.rate_limits {
+        let key = LimiterKey {
+            names: site.names.clone(),
+            limit: limit.clone(),
+            key_header: config.rate_limit_key.clone(),
+            trusted: config.rate_limit_trusted.clone(),
+        };
+        let limiter = reuse(&old.limiters, &mut kept.limiters, key, || {
+            let mut limiter = RateLimiter::new(limit.per_second, limit.burst);
+            if let Some(header) = &config.rate_limit_key {
+                limiter = limiter.key_header(header.as_str());
+            }
+            for &proxy in &config.rate_limit_trusted {
+                limiter = limiter.trust(proxy);
+            }
+            limiter
+        });
+        router = router.limit(&limit.prefix, limiter);
+    }
+    router
 }
 
 // //multi-threading
@@ -79,4 +156,4 @@ fn handle_connection(mut stream : TcpStream) {
 
 // fn mthread_handle_connection(mut stream : TcpStream ) {
 
-// }
\ No newline at end of file
+// }
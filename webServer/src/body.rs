@@ -0,0 +1,392 @@
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::de::DeserializeOwned;
+
+use crate::error::HttpError;
+use crate::request::Request;
+use crate::url::Params;
+
+//the headers of one part, Content-Disposition and Content-Type, are never anywhere near this
+const MAX_PART_HEAD: usize = 8 * 1024;
+
+/// Decoded `application/x-www-form-urlencoded` fields. Same format as a query string.
+pub type Form = Params;
+
+/// A file part of a `multipart/form-data` body, already written to disk.
+///
+/// The file is left in place; the handler decides whether to move it or delete it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UploadedFile {
+    pub field: String,
+    /// The file name the client sent, not trusted for anything but display.
+    pub file_name: String,
+    pub content_type: Option<String>,
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Multipart {
+    pub fields: Form,
+    pub files: Vec<UploadedFile>,
+}
+
+//typed body extraction. These check the Content-Type first, so a handler asking for a form
+//never tries to decode JSON by accident, and report 415 when the client sent something else.
+impl Request {
+    pub fn form(&self) -> Result<Form, HttpError> {
+        self.expect_content_type("application/x-www-form-urlencoded")?;
+        let body = std::str::from_utf8(&self.body)
+            .map_err(|_| HttpError::bad_request("form body is not valid UTF-8"))?;
+        Params::parse(body)
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, HttpError> {
+        match self.content_type() {
+            Some(ref t) if t == "application/json" || t.ends_with("+json") => {}
+            _ => return Err(HttpError::unsupported_media_type("application/json")),
+        }
+        serde_json::from_slice(&self.body)
+            .map_err(|e| HttpError::bad_request(format!("invalid JSON body: {}", e)))
+    }
+
+    /// Splits a `multipart/form-data` body into its text fields and file parts,
+    /// writing each file part into `upload_dir`.
+    pub fn multipart(&self, upload_dir: &Path) -> Result<Multipart, HttpError> {
+        self.multipart_from(&mut &self.body[..], upload_dir, u64::MAX)
+    }
+
+    /// Like `multipart`, for a handler that streams the body (see `Handler::streams_body`):
+    /// parts are read from `body` as it arrives, and file parts go to disk without being held
+    /// in memory. A part bigger than `max_part` bytes is refused with 413.
+    pub fn multipart_from(&self, body: &mut dyn Read, upload_dir: &Path, max_part: u64) -> Result<Multipart, HttpError> {
+        self.expect_content_type("multipart/form-data")?;
+        let boundary = self
+            .header("Content-Type")
+            .and_then(|v| header_param(v, "boundary"))
+            .ok_or_else(|| HttpError::bad_request("multipart body without a boundary"))?;
+        parse_multipart(body, &boundary, upload_dir, max_part)
+    }
+
+    fn expect_content_type(&self, expected: &str) -> Result<(), HttpError> {
+        match self.content_type() {
+            Some(ref t) if t == expected => Ok(()),
+            _ => Err(HttpError::unsupported_media_type(expected)),
+        }
+    }
+}
+
+//pulls `name=value` or `name="value"` out of a header like `form-data; name="a"; filename="b"`
+fn header_param(header: &str, name: &str) -> Option<String> {
+    header.split(';').skip(1).find_map(|param| {
+        let param = param.trim();
+        let i = param.find('=')?;
+        if !param[..i].trim().eq_ignore_ascii_case(name) {
+            return None;
+        }
+        let value = param[i + 1..].trim();
+        Some(value.trim_matches('"').to_string())
+    })
+}
+
+fn parse_multipart(body: &mut dyn Read, boundary: &str, upload_dir: &Path, max_part: u64) -> Result<Multipart, HttpError> {
+    let malformed = || HttpError::bad_request("malformed multipart body");
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut next_delimiter = b"\r\n".to_vec();
+    next_delimiter.extend_from_slice(&delimiter);
+
+    let mut multipart = Multipart::default();
+    let mut parts = Parts {
+        reader: body,
+        buf: Vec::new(),
+    };
+    //the preamble before the first delimiter is thrown away
+    if !parts.until(&delimiter, &mut |_| Ok(()))? {
+        return Err(malformed());
+    }
+
+    loop {
+        //"--" right after a delimiter closes the body
+        if parts.eat(b"--")? {
+            break;
+        }
+        if !parts.eat(b"\r\n")? {
+            return Err(malformed());
+        }
+
+        let mut head = Vec::new();
+        let found = parts.until(b"\r\n\r\n", &mut |bytes| {
+            head.extend_from_slice(bytes);
+            if head.len() > MAX_PART_HEAD {
+                return Err(HttpError::new(431, "multipart part headers too long"));
+            }
+            Ok(())
+        })?;
+        if !found {
+            return Err(malformed());
+        }
+        let head = std::str::from_utf8(&head).map_err(|_| malformed())?;
+
+        let mut disposition = None;
+        let mut content_type = None;
+        for line in head.split("\r\n") {
+            let i = line.find(':').ok_or_else(malformed)?;
+            let (name, value) = (line[..i].trim(), line[i + 1..].trim());
+            if name.eq_ignore_ascii_case("Content-Disposition") {
+                disposition = Some(value);
+            } else if name.eq_ignore_ascii_case("Content-Type") {
+                content_type = Some(value.to_string());
+            }
+        }
+        let disposition = disposition.ok_or_else(malformed)?;
+        let field = header_param(disposition, "name").ok_or_else(malformed)?;
+
+        match header_param(disposition, "filename") {
+            Some(file_name) => {
+                let path = upload_dir.join(upload_name());
+                let size = match write_upload(&mut parts, &next_delimiter, &path, max_part) {
+                    Ok(size) => size,
+                    Err(err) => {
+                        let _ = fs::remove_file(&path);
+                        return Err(err);
+                    }
+                };
+                multipart.files.push(UploadedFile {
+                    field,
+                    file_name,
+                    content_type,
+                    path,
+                    size,
+                });
+            }
+            None => {
+                let mut content = Vec::new();
+                let found = parts.until(&next_delimiter, &mut |bytes| {
+                    content.extend_from_slice(bytes);
+                    if content.len() as u64 > max_part {
+                        return Err(HttpError::new(413, format!("multipart field is larger than {} bytes", max_part)));
+                    }
+                    Ok(())
+                })?;
+                if !found {
+                    return Err(malformed());
+                }
+                let value = String::from_utf8(content)
+                    .map_err(|_| HttpError::bad_request("multipart field is not valid UTF-8"))?;
+                multipart.fields.push(field, value);
+            }
+        }
+    }
+
+    Ok(multipart)
+}
+
+//the body as it is read, with what has been read but not dealt with yet. Only a delimiter's
+//length is held back, so parts go wherever they are going without being collected first
+struct Parts<'a> {
+    reader: &'a mut dyn Read,
+    buf: Vec<u8>,
+}
+
+impl Parts<'_> {
+    //reads some more into buf, false at the end of the body
+    fn fill(&mut self) -> Result<bool, HttpError> {
+        let mut chunk = [0; 8 * 1024];
+        let read = self
+            .reader
+            .read(&mut chunk)
+            .map_err(|e| HttpError::bad_request(format!("could not read the request body: {}", e)))?;
+        self.buf.extend_from_slice(&chunk[..read]);
+        Ok(read > 0)
+    }
+
+    //consumes `bytes` if the body goes on with them
+    fn eat(&mut self, bytes: &[u8]) -> Result<bool, HttpError> {
+        while self.buf.len() < bytes.len() {
+            if !self.fill()? {
+                return Ok(false);
+            }
+        }
+        let found = self.buf.starts_with(bytes);
+        if found {
+            self.buf.drain(..bytes.len());
+        }
+        Ok(found)
+    }
+
+    //hands everything before `needle` to `out` and consumes the needle too; false if the
+    //body ended without one
+    fn until(&mut self, needle: &[u8], out: &mut dyn FnMut(&[u8]) -> Result<(), HttpError>) -> Result<bool, HttpError> {
+        loop {
+            if let Some(i) = find(&self.buf, needle) {
+                out(&self.buf[..i])?;
+                self.buf.drain(..i + needle.len());
+                return Ok(true);
+            }
+            //the end of buf could be the start of the needle, keep that much back
+            let keep = (needle.len() - 1).min(self.buf.len());
+            let done = self.buf.len() - keep;
+            out(&self.buf[..done])?;
+            self.buf.drain(..done);
+            if !self.fill()? {
+                return Ok(false);
+            }
+        }
+    }
+}
+
+//we never use the client's file name on disk, it could contain "../" or clash with another upload
+fn upload_name() -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    format!(
+        "upload-{}-{}-{}",
+        std::process::id(),
+        nanos,
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+//written piece by piece as the part is read, refused with 413 once it goes over `max`
+fn write_upload(parts: &mut Parts, delimiter: &[u8], path: &Path, max: u64) -> Result<u64, HttpError> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let mut size = 0;
+    let found = parts.until(delimiter, &mut |bytes| {
+        size += bytes.len() as u64;
+        if size > max {
+            return Err(HttpError::new(413, format!("uploaded file is larger than {} bytes", max)));
+        }
+        file.write_all(bytes)?;
+        Ok(())
+    })?;
+    if !found {
+        return Err(HttpError::bad_request("malformed multipart body"));
+    }
+    file.flush()?;
+    Ok(size)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::fs;
+
+    fn request(content_type: &str, body: &str) -> Request {
+        let mut request = Request::new("POST", "/");
+        request.headers.append("Content-Type", content_type);
+        request.body = body.as_bytes().to_vec();
+        request
+    }
+
+    #[test]
+    fn decodes_urlencoded_form() {
+        let request = request(
+            "application/x-www-form-urlencoded",
+            "name=Jane+Doe&tag=a&tag=b%26c&empty=&caf%C3%A9=%E2%9C%93",
+        );
+        let form = request.form().unwrap();
+
+        assert_eq!(Some("Jane Doe"), form.get("name"));
+        assert_eq!(vec!["a", "b&c"], form.get_all("tag").collect::<Vec<_>>());
+        assert_eq!(Some(""), form.get("empty"));
+        assert_eq!(Some("✓"), form.get("café"));
+    }
+
+    #[test]
+    fn wrong_content_type_is_415() {
+        let request = request("text/plain", "a=b");
+        assert_eq!(415, request.form().unwrap_err().status());
+    }
+
+    #[test]
+    fn decodes_json_into_user_type() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Login {
+            user: String,
+            remember: bool,
+        }
+
+        let request = request("application/json; charset=utf-8", r#"{"user":"ann","remember":true}"#);
+        let login: Login = request.json().unwrap();
+        assert_eq!(Login { user: String::from("ann"), remember: true }, login);
+
+        let broken = self::request("application/json", "{");
+        assert_eq!(400, broken.json::<Login>().unwrap_err().status());
+    }
+
+    #[test]
+    fn multipart_writes_files_to_disk() {
+        let dir = std::env::temp_dir().join(format!("webserver-multipart-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let body = "preamble\r\n--XyZ\r\n\
+Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+holiday\r\n--XyZ\r\n\
+Content-Disposition: form-data; name=\"photo\"; filename=\"../beach.txt\"\r\n\
+Content-Type: text/plain\r\n\r\n\
+sand\r\nand sea\r\n--XyZ--\r\n";
+        let request = request("multipart/form-data; boundary=XyZ", body);
+        let multipart = request.multipart(&dir).unwrap();
+
+        assert_eq!(Some("holiday"), multipart.fields.get("title"));
+        assert_eq!(1, multipart.files.len());
+        let file = &multipart.files[0];
+        assert_eq!("photo", file.field);
+        assert_eq!("../beach.txt", file.file_name);
+        assert_eq!(Some("text/plain"), file.content_type.as_deref());
+        assert!(file.path.starts_with(&dir));
+        assert_eq!("sand\r\nand sea", fs::read_to_string(&file.path).unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    //hands out one byte at a time, so every delimiter is split across reads
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match (self.0.split_first(), buf.first_mut()) {
+                (Some((&byte, rest)), Some(slot)) => {
+                    *slot = byte;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn multipart_streams_parts_up_to_a_limit() {
+        let dir = std::env::temp_dir().join(format!("webserver-multipart-stream-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let body = "--XyZ\r\n\
+Content-Disposition: form-data; name=\"doc\"; filename=\"a.txt\"\r\n\r\n\
+--XyZ-ish\r\n--XyZ--\r\n";
+        let request = request("multipart/form-data; boundary=XyZ", "");
+        let multipart = request.multipart_from(&mut Trickle(body.as_bytes()), &dir, 9).unwrap();
+        assert_eq!(9, multipart.files[0].size);
+        assert_eq!("--XyZ-ish", fs::read_to_string(&multipart.files[0].path).unwrap());
+
+        //a part over the limit is refused, and what was written of it removed again
+        let err = request.multipart_from(&mut Trickle(body.as_bytes()), &dir, 8).unwrap_err();
+        assert_eq!(413, err.status());
+        assert_eq!(1, fs::read_dir(&dir).unwrap().count());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,381 @@
+use std::io::prelude::*;
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::error::HttpError;
+use crate::headers::Headers;
+use crate::request::Request;
+use crate::response::Response;
+use crate::router::Handler;
+
+/// Runs the executables in a directory as CGI/1.1 scripts: `/cgi-bin/hello.sh/extra?x=1`
+/// starts `hello.sh` with `PATH_INFO=/extra` and `QUERY_STRING=x=1` in its environment and the
+/// request body on its stdin. What it prints, a block of headers and then the body, is
+/// streamed back to the client as it comes.
+///
+/// A script still running after `timeout` is killed, and so is one whose client went away
+/// before it finished. The client is only found gone when writing to it fails, so a script
+/// that prints nothing, not even its headers, runs until the timeout whatever the client does.
+pub struct Cgi {
+    prefix: String,
+    dir: PathBuf,
+    timeout: Duration,
+}
+
+impl Cgi {
+    /// Scripts in `dir`, served under `prefix`; mount the handler at the same prefix.
+    pub fn new(prefix: &str, dir: impl Into<PathBuf>) -> Cgi {
+        Cgi {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            dir: dir.into(),
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Cgi {
+        self.timeout = timeout;
+        self
+    }
+
+    //`/cgi-bin/hello.sh/extra` -> ("hello.sh", "/extra")
+    fn script<'a>(&self, path: &'a str) -> Option<(&'a str, &'a str)> {
+        let rest = path.strip_prefix(&self.prefix)?.strip_prefix('/')?;
+        let (name, path_info) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, ""),
+        };
+        if name.is_empty() || name.starts_with('.') {
+            return None;
+        }
+        Some((name, path_info))
+    }
+
+    fn environment(&self, request: &Request, name: &str, path_info: &str) -> Vec<(String, String)> {
+        let host = request.header("Host").unwrap_or("localhost");
+        let (server_name, server_port) = match host.rfind(':') {
+            Some(i) if !host[i..].contains(']') => (&host[..i], &host[i + 1..]),
+            _ => (host, "80"),
+        };
+        let query = request.target.find('?').map_or("", |i| &request.target[i + 1..]);
+
+        let mut env = vec![
+            ("GATEWAY_INTERFACE", String::from("CGI/1.1")),
+            ("SERVER_PROTOCOL", request.version.clone()),
+            ("SERVER_SOFTWARE", String::from("webServer")),
+            ("SERVER_NAME", server_name.to_string()),
+            ("SERVER_PORT", server_port.to_string()),
+            ("REQUEST_METHOD", request.method.clone()),
+            ("SCRIPT_NAME", format!("{}/{}", self.prefix, name)),
+            ("PATH_INFO", path_info.to_string()),
+            ("QUERY_STRING", query.to_string()),
+            ("CONTENT_LENGTH", request.body.len().to_string()),
+            ("CONTENT_TYPE", request.header("Content-Type").unwrap_or("").to_string()),
+        ];
+        if let Some(peer) = request.peer {
+            env.push(("REMOTE_ADDR", peer.ip().to_string()));
+            env.push(("REMOTE_PORT", peer.port().to_string()));
+        }
+        //scripts find their interpreters through PATH, but nothing else of ours leaks in
+        if let Some(path) = std::env::var_os("PATH") {
+            env.push(("PATH", path.to_string_lossy().into_owned()));
+        }
+
+        let mut env: Vec<(String, String)> = env.into_iter().map(|(k, v)| (k.to_string(), v)).collect();
+        for (name, value) in request.headers.iter() {
+            //a `Proxy` header would become HTTP_PROXY, which many HTTP libraries take as their proxy
+            if name.eq_ignore_ascii_case("Content-Type")
+                || name.eq_ignore_ascii_case("Content-Length")
+                || name.eq_ignore_ascii_case("Proxy")
+            {
+                continue;
+            }
+            let key = format!("HTTP_{}", name.to_ascii_uppercase().replace('-', "_"));
+            match env.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, existing)) => {
+                    existing.push_str(", ");
+                    existing.push_str(value);
+                }
+                None => env.push((key, value.to_string())),
+            }
+        }
+        env
+    }
+}
+
+impl Handler for Cgi {
+    fn handle(&self, request: &Request) -> Result<Response, HttpError> {
+        let (name, path_info) = self.script(&request.path).ok_or_else(HttpError::not_found)?;
+        let script = self.dir.join(name);
+        if !script.is_file() {
+            return Err(HttpError::not_found());
+        }
+
+        let mut command = Command::new(&script);
+        //in a process group of its own, so whatever the script starts can be killed along with it
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        let mut child = command
+            .current_dir(&self.dir)
+            .env_clear()
+            .envs(self.environment(request, name, path_info))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+
+        //fed from another thread: a script may start printing before it has read everything,
+        //and with both pipes full we would wait on each other forever
+        let mut stdin = child.stdin.take().unwrap();
+        let body = request.body.clone();
+        thread::spawn(move || stdin.write_all(&body));
+
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let process = Process::watch(child, self.timeout);
+        let headers = match read_cgi_headers(&mut stdout) {
+            Ok(headers) => headers,
+            Err(_) if process.timed_out.load(Ordering::SeqCst) => {
+                return Err(HttpError::new(504, format!("{} did not answer in time", name)));
+            }
+            Err(e) => return Err(HttpError::new(502, format!("{} sent a malformed response", name)).with_source(e)),
+        };
+
+        let status = match headers.get("Status") {
+            Some(status) => status
+                .split_whitespace()
+                .next()
+                .and_then(|code| code.parse::<u16>().ok())
+                .filter(|code| (100..600).contains(code))
+                .ok_or_else(|| HttpError::new(502, format!("{} sent a bad Status: {}", name, status)))?,
+            None if headers.contains("Location") => 302,
+            None => 200,
+        };
+        let mut response = Response::new(status).with_stream(Body { stdout, process }, None);
+        for (name, value) in headers.iter().filter(|(name, _)| !name.eq_ignore_ascii_case("Status")) {
+            response.headers.append(name, value);
+        }
+        Ok(response)
+    }
+}
+
+//the running script, killed when the response body is dropped (done, or the client hung up)
+//or when the watchdog thread runs out of patience, whichever comes first
+struct Process {
+    child: Arc<Mutex<Script>>,
+    timed_out: Arc<AtomicBool>,
+    done: Option<mpsc::Sender<()>>,
+}
+
+//reaped says the id may belong to another process by now; it is only set with the lock held,
+//so the watchdog can't look, find it unset, and signal after Drop reaped the child
+struct Script {
+    child: Child,
+    reaped: bool,
+}
+
+impl Process {
+    fn watch(child: Child, timeout: Duration) -> Process {
+        let child = Arc::new(Mutex::new(Script { child, reaped: false }));
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let (done, finished) = mpsc::channel::<()>();
+        {
+            let child = Arc::clone(&child);
+            let timed_out = Arc::clone(&timed_out);
+            thread::spawn(move || {
+                if let Err(RecvTimeoutError::Timeout) = finished.recv_timeout(timeout) {
+                    timed_out.store(true, Ordering::SeqCst);
+                    kill(&mut child.lock().unwrap());
+                }
+            });
+        }
+        Process {
+            child,
+            timed_out,
+            done: Some(done),
+        }
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        self.done.take();
+        let mut script = self.child.lock().unwrap();
+        //it has usually exited by now, in which case there is nothing to kill but the zombie to reap
+        if let Ok(None) = script.child.try_wait() {
+            kill(&mut script);
+        }
+        let _ = script.child.wait();
+        script.reaped = true;
+    }
+}
+
+//not reaped yet, so its id (and group id) can't have been reused
+#[cfg(unix)]
+fn kill(script: &mut Script) {
+    if script.reaped {
+        return;
+    }
+    unsafe {
+        libc::kill(-(script.child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = script.child.kill();
+}
+
+#[cfg(not(unix))]
+fn kill(script: &mut Script) {
+    if !script.reaped {
+        let _ = script.child.kill();
+    }
+}
+
+struct Body {
+    stdout: BufReader<ChildStdout>,
+    process: Process,
+}
+
+impl Read for Body {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stdout.read(buf)?;
+        if n == 0 && self.process.timed_out.load(Ordering::SeqCst) {
+            //the script was cut off mid-body; failing the copy makes sure the client can't
+            //mistake the truncated output for all of it
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "CGI script timed out"));
+        }
+        Ok(n)
+    }
+}
+
+fn read_cgi_headers<R: BufRead>(reader: &mut R) -> io::Result<Headers> {
+    let bad = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let mut headers = Headers::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(bad("output ended before the blank line after the headers"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let i = line.find(':').ok_or_else(|| bad("malformed header line"))?;
+        headers.append(line[..i].trim(), line[i + 1..].trim());
+    }
+    if headers.is_empty() {
+        return Err(bad("no headers"));
+    }
+    Ok(headers)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::response::Body as ResponseBody;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::Instant;
+
+    fn scripts(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("webserver-cgi-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (file, source) in files {
+            let path = dir.join(file);
+            fs::write(&path, source).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        dir
+    }
+
+    fn body(response: &mut Response) -> io::Result<String> {
+        let mut out = String::new();
+        if let ResponseBody::Stream { reader, .. } = &mut response.body {
+            reader.read_to_string(&mut out)?;
+        }
+        Ok(out)
+    }
+
+    #[test]
+    fn passes_the_request_in_environment_and_stdin() {
+        let dir = scripts(
+            "env",
+            &[(
+                "echo.sh",
+                "#!/bin/sh\nprintf 'Content-Type: text/plain\\r\\nX-Script: yes\\r\\n\\r\\n'\n\
+echo \"$REQUEST_METHOD $SCRIPT_NAME $PATH_INFO $QUERY_STRING $CONTENT_LENGTH $HTTP_X_TOKEN [$HTTP_PROXY] $REMOTE_ADDR\"\n\
+cat\n",
+            )],
+        );
+        let cgi = Cgi::new("/cgi-bin", &dir);
+        let mut request = Request::new("POST", "/cgi-bin/echo.sh/extra/bits?x=1&y=2");
+        request.headers.append("X-Token", "abc");
+        request.headers.append("Proxy", "http://evil.example");
+        request.peer = Some("192.0.2.9:4000".parse().unwrap());
+        request.body = b"hello from stdin".to_vec();
+
+        let mut response = cgi.handle(&request).unwrap();
+        assert_eq!(200, response.status);
+        assert_eq!(Some("yes"), response.headers.get("X-Script"));
+        assert_eq!(
+            "POST /cgi-bin/echo.sh /extra/bits x=1&y=2 16 abc [] 192.0.2.9\nhello from stdin",
+            body(&mut response).unwrap()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn honours_status_and_location() {
+        let dir = scripts(
+            "status",
+            &[
+                ("gone.sh", "#!/bin/sh\nprintf 'Status: 410 Gone\\n\\nbye'\n"),
+                ("move.sh", "#!/bin/sh\nprintf 'Location: /elsewhere\\n\\n'\n"),
+                ("broken.sh", "#!/bin/sh\necho no headers at all\n"),
+            ],
+        );
+        let cgi = Cgi::new("/cgi-bin/", &dir);
+        let run = |target: &str| cgi.handle(&Request::new("GET", target));
+
+        let mut gone = run("/cgi-bin/gone.sh").unwrap();
+        assert_eq!(410, gone.status);
+        assert!(!gone.headers.contains("Status"));
+        assert_eq!("bye", body(&mut gone).unwrap());
+        assert_eq!(302, run("/cgi-bin/move.sh").unwrap().status);
+        assert_eq!(502, run("/cgi-bin/broken.sh").unwrap_err().status());
+        assert_eq!(404, run("/cgi-bin/missing.sh").unwrap_err().status());
+        assert_eq!(404, run("/cgi-bin/").unwrap_err().status());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn kills_slow_scripts_and_abandoned_ones() {
+        let dir = scripts(
+            "kill",
+            &[
+                ("slow.sh", "#!/bin/sh\nsleep 5\necho\n"),
+                ("endless.sh", "#!/bin/sh\necho $$ > pid\nprintf 'Content-Type: text/plain\\n\\n'\nwhile :; do echo tick; sleep 0.01; done\n"),
+            ],
+        );
+        let cgi = Cgi::new("/cgi-bin", &dir).timeout(Duration::from_millis(200));
+        let start = Instant::now();
+        assert_eq!(504, cgi.handle(&Request::new("GET", "/cgi-bin/slow.sh")).unwrap_err().status());
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        //the client reads a little, then goes away
+        let cgi = Cgi::new("/cgi-bin", &dir).timeout(Duration::from_secs(30));
+        let mut response = cgi.handle(&Request::new("GET", "/cgi-bin/endless.sh")).unwrap();
+        if let ResponseBody::Stream { reader, .. } = &mut response.body {
+            let mut first = [0u8; 5];
+            reader.read_exact(&mut first).unwrap();
+            assert_eq!(b"tick\n", &first);
+        }
+        let pid = fs::read_to_string(dir.join("pid")).unwrap();
+        drop(response);
+        let alive = Command::new("kill").args(["-0", pid.trim()]).stderr(Stdio::null()).status().unwrap();
+        assert!(!alive.success(), "script {} is still running", pid.trim());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,511 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::errorpages::Mode;
+use crate::request::Limits;
+
+/// Where the HTTPS listener binds and the PEM files it presents.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsSettings {
+    pub address: String,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// A path prefix forwarded to one or more upstream servers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProxyRoute {
+    pub prefix: String,
+    pub upstreams: Vec<String>,
+}
+
+/// Requests allowed under a path prefix, per client: `per_second` on average, `burst` at once.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimit {
+    pub prefix: String,
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+/// What is served for one set of host names: the default site, or a virtual host.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Site {
+    /// Host names this site answers to; empty for the default site, which answers the rest.
+    pub names: Vec<String>,
+    /// The document root static pages are read from.
+    pub root: PathBuf,
+    /// The page served for a directory, `index.html` unless set.
+    pub index: String,
+    /// Error page templates under the root, by status or class (`404`, `5xx`).
+    /// `404.html` is used for 404s unless set otherwise.
+    pub error_pages: Vec<(String, String)>,
+    /// List the contents of directories under the root that have no index page.
+    pub autoindex: bool,
+    pub proxies: Vec<ProxyRoute>,
+    pub rate_limits: Vec<RateLimit>,
+    /// Path prefixes whose requests run the CGI scripts in a directory.
+    pub cgi: Vec<(String, PathBuf)>,
+}
+
+impl Default for Site {
+    fn default() -> Site {
+        Site {
+            names: Vec::new(),
+            root: PathBuf::from("public"),
+            index: String::from("index.html"),
+            error_pages: vec![(String::from("404"), String::from("404.html"))],
+            autoindex: false,
+            proxies: Vec::new(),
+            rate_limits: Vec::new(),
+            cgi: Vec::new(),
+        }
+    }
+}
+
+/// Server settings, read from a `key = value` file:
+///
+/// ```text
+/// # lines starting with '#' are comments
+/// address = 127.0.0.1:7878
+/// threads = 4
+/// max_body = 1048576
+/// # seconds a client may keep us waiting for the next bytes of a request, or to take a response
+/// timeout = 30
+/// # WebSocket and event stream connections open at once, each on a thread of its own
+/// max_streams = 64
+/// cache_size = 16777216
+/// metrics_path = /metrics
+///
+/// tls_address = 127.0.0.1:7879
+/// tls_cert = cert.pem
+/// tls_key = key.pem
+/// redirect_http = true
+///
+/// # tell clients apart by a header instead of the IP, in requests from these proxies
+/// rate_limit_key = X-Api-Key
+/// rate_limit_trusted = 127.0.0.1 10.0.0.2
+/// # production hides server error details from error pages, development shows them
+/// mode = production
+/// # seconds a CGI script may run
+/// cgi_timeout = 30
+///
+/// # the default site, for requests to any host not listed below
+/// root = public
+/// index = index.html
+/// # may be repeated, by status or by class
+/// error_page = 404 404.html
+/// error_page = 5xx 50x.html
+/// autoindex = false
+/// # may be repeated, one line per prefix
+/// proxy = /api 127.0.0.1:9000 127.0.0.1:9001
+/// # prefix, requests per second, burst
+/// rate_limit = / 20 40
+/// rate_limit = /api/login 0.2 5
+/// # prefix and the directory its scripts are in
+/// cgi = /cgi-bin cgi-bin
+///
+/// # a virtual host: the site keys above, for requests with one of these Host names
+/// [host docs.internal *.docs.internal]
+/// root = sites/docs
+/// autoindex = true
+/// ```
+///
+/// Every key is optional except a virtual host's `root`; the defaults serve plain HTTP on
+/// 127.0.0.1:7878 with 4 threads, from the `public` directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub address: String,
+    pub threads: usize,
+    pub max_body: usize,
+    /// Read and write timeout on client sockets; upgraded connections have no read timeout.
+    pub timeout: Duration,
+    /// Most WebSocket and event stream connections at once. They run outside the pool, so idle
+    /// ones don't keep its workers from other requests; past this many they are answered 503.
+    pub max_streams: usize,
+    /// Bytes of static files kept in memory.
+    pub cache_size: usize,
+    /// Where cache counters are served, in the Prometheus text format. Off unless set.
+    pub metrics_path: Option<String>,
+    pub tls: Option<TlsSettings>,
+    /// Answer every plain HTTP request with a redirect to the HTTPS listener.
+    pub redirect_http: bool,
+    pub rate_limit_key: Option<String>,
+    /// Peers whose `rate_limit_key` header is believed, the proxies that set or check it.
+    pub rate_limit_trusted: Vec<IpAddr>,
+    pub mode: Mode,
+    pub cgi_timeout: Duration,
+    pub site: Site,
+    /// Virtual hosts, tried in order before falling back to `site`.
+    pub hosts: Vec<Site>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            address: String::from("127.0.0.1:7878"),
+            threads: 4,
+            max_body: Limits::default().max_body,
+            timeout: Duration::from_secs(30),
+            max_streams: 64,
+            cache_size: 16 * 1024 * 1024,
+            metrics_path: None,
+            tls: None,
+            redirect_http: false,
+            rate_limit_key: None,
+            rate_limit_trusted: Vec::new(),
+            mode: Mode::Production,
+            cgi_timeout: Duration::from_secs(30),
+            site: Site::default(),
+            hosts: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Invalid { line: usize, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "cannot read config: {}", err),
+            ConfigError::Invalid { line, message } => write!(f, "config line {}: {}", line, message),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io(err) => Some(err),
+            ConfigError::Invalid { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> ConfigError {
+        ConfigError::Io(err)
+    }
+}
+
+impl Config {
+    /// Reads a config file. Relative paths (document roots, certificates) are resolved against
+    /// the file's directory.
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let mut config = Config::parse(&fs::read_to_string(path)?)?;
+        if let Some(dir) = path.parent() {
+            for site in config.sites_mut() {
+                site.root = dir.join(&site.root);
+                for (_, scripts) in site.cgi.iter_mut() {
+                    *scripts = dir.join(&scripts);
+                }
+            }
+            if let Some(tls) = config.tls.as_mut() {
+                tls.cert = dir.join(&tls.cert);
+                tls.key = dir.join(&tls.key);
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn parse(contents: &str) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+        let (mut tls_address, mut tls_cert, mut tls_key) = (None, None, None);
+
+        for (i, line) in contents.lines().enumerate() {
+            let line_no = i + 1;
+            let invalid = |message: String| ConfigError::Invalid { line: line_no, message };
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') {
+                config.hosts.push(parse_section(line).map_err(invalid)?);
+                continue;
+            }
+            let (key, value) = match line.find('=') {
+                Some(i) => (line[..i].trim(), line[i + 1..].trim()),
+                None => return Err(invalid(format!("expected `key = value`, got `{}`", line))),
+            };
+
+            //keys after a [host] line belong to that host, until the next one
+            let in_host = !config.hosts.is_empty();
+            let site = config.hosts.last_mut().unwrap_or(&mut config.site);
+            match key {
+                "root" => site.root = PathBuf::from(value),
+                "index" => site.index = value.to_string(),
+                "error_page" => site.error_pages.push(parse_error_page(value).map_err(invalid)?),
+                "autoindex" => site.autoindex = parse_bool(value).map_err(invalid)?,
+                "proxy" => site.proxies.push(parse_proxy(value).map_err(invalid)?),
+                "rate_limit" => site.rate_limits.push(parse_rate_limit(value).map_err(invalid)?),
+                "cgi" => site.cgi.push(parse_cgi(value).map_err(invalid)?),
+                _ if in_host => return Err(invalid(format!("`{}` applies to the whole server, not one host", key))),
+                "address" => config.address = value.to_string(),
+                "threads" => config.threads = parse_number(value).map_err(invalid)?,
+                "max_body" => config.max_body = parse_number(value).map_err(invalid)?,
+                "timeout" => config.timeout = Duration::from_secs(parse_number(value).map_err(invalid)? as u64),
+                "max_streams" => config.max_streams = parse_number(value).map_err(invalid)?,
+                "cache_size" => config.cache_size = parse_number(value).map_err(invalid)?,
+                "metrics_path" if value.starts_with('/') => config.metrics_path = Some(value.to_string()),
+                "metrics_path" => return Err(invalid(format!("`{}` should be a path", value))),
+                "tls_address" => tls_address = Some(value.to_string()),
+                "tls_cert" => tls_cert = Some(PathBuf::from(value)),
+                "tls_key" => tls_key = Some(PathBuf::from(value)),
+                "redirect_http" => config.redirect_http = parse_bool(value).map_err(invalid)?,
+                "rate_limit_key" => config.rate_limit_key = Some(value.to_string()),
+                "rate_limit_trusted" => config.rate_limit_trusted = parse_addresses(value).map_err(invalid)?,
+                "cgi_timeout" => config.cgi_timeout = Duration::from_secs(parse_number(value).map_err(invalid)? as u64),
+                "mode" => config.mode = parse_mode(value).map_err(invalid)?,
+                _ => return Err(invalid(format!("unknown key `{}`", key))),
+            }
+        }
+
+        if let Some(host) = config.hosts.iter().find(|h| h.root.as_os_str().is_empty()) {
+            return Err(ConfigError::Invalid {
+                line: 0,
+                message: format!("host {} needs a root", host.names.join(" ")),
+            });
+        }
+        if config.threads == 0 {
+            return Err(ConfigError::Invalid { line: 0, message: String::from("threads must be at least 1") });
+        }
+        if config.timeout.is_zero() {
+            return Err(ConfigError::Invalid { line: 0, message: String::from("timeout must be at least 1") });
+        }
+        config.tls = match (tls_address, tls_cert, tls_key) {
+            (None, None, None) => None,
+            (address, Some(cert), Some(key)) => Some(TlsSettings {
+                address: address.unwrap_or_else(|| String::from("127.0.0.1:7879")),
+                cert,
+                key,
+            }),
+            _ => {
+                return Err(ConfigError::Invalid {
+                    line: 0,
+                    message: String::from("TLS needs both tls_cert and tls_key"),
+                })
+            }
+        };
+        if config.redirect_http && config.tls.is_none() {
+            return Err(ConfigError::Invalid {
+                line: 0,
+                message: String::from("redirect_http needs a TLS listener to redirect to"),
+            });
+        }
+
+        Ok(config)
+    }
+
+    /// The default site and every virtual host.
+    pub fn sites(&self) -> impl Iterator<Item = &Site> {
+        std::iter::once(&self.site).chain(self.hosts.iter())
+    }
+
+    fn sites_mut(&mut self) -> impl Iterator<Item = &mut Site> {
+        std::iter::once(&mut self.site).chain(self.hosts.iter_mut())
+    }
+
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_body: self.max_body,
+            ..Limits::default()
+        }
+    }
+}
+
+fn parse_number(value: &str) -> Result<usize, String> {
+    value.parse().map_err(|_| format!("`{}` is not a number", value))
+}
+
+//`[host name...]`; the root is left empty so a host without one can be reported
+fn parse_section(line: &str) -> Result<Site, String> {
+    let inner = match line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+        Some(inner) => inner,
+        None => return Err(format!("`{}` is missing its closing ]", line)),
+    };
+    let mut words = inner.split_whitespace();
+    if words.next() != Some("host") {
+        return Err(format!("unknown section `{}`, expected [host name...]", line));
+    }
+    let names: Vec<String> = words.map(|name| name.to_ascii_lowercase()).collect();
+    if names.is_empty() {
+        return Err(String::from("[host] needs at least one name"));
+    }
+    Ok(Site {
+        names,
+        root: PathBuf::new(),
+        ..Site::default()
+    })
+}
+
+fn parse_proxy(value: &str) -> Result<ProxyRoute, String> {
+    let mut parts = value.split_whitespace();
+    let prefix = match parts.next() {
+        Some(prefix) if prefix.starts_with('/') => prefix.to_string(),
+        _ => return Err(format!("`{}` should be a path prefix followed by upstream addresses", value)),
+    };
+    let upstreams: Vec<String> = parts.map(String::from).collect();
+    if upstreams.is_empty() {
+        return Err(format!("no upstream given for {}", prefix));
+    }
+    Ok(ProxyRoute { prefix, upstreams })
+}
+
+fn parse_rate_limit(value: &str) -> Result<RateLimit, String> {
+    let usage = || format!("`{}` should be a path prefix, requests per second and a burst size", value);
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    match parts[..] {
+        [prefix, per_second, burst] if prefix.starts_with('/') => {
+            let per_second: f64 = per_second.parse().map_err(|_| usage())?;
+            let burst: u32 = burst.parse().map_err(|_| usage())?;
+            if !per_second.is_finite() || per_second <= 0.0 || burst == 0 {
+                return Err(format!("the rate and burst for {} must be above zero", prefix));
+            }
+            Ok(RateLimit {
+                prefix: prefix.to_string(),
+                per_second,
+                burst,
+            })
+        }
+        _ => Err(usage()),
+    }
+}
+
+fn parse_addresses(value: &str) -> Result<Vec<IpAddr>, String> {
+    value
+        .split_whitespace()
+        .map(|ip| ip.parse().map_err(|_| format!("`{}` is not an IP address", ip)))
+        .collect()
+}
+
+fn parse_cgi(value: &str) -> Result<(String, PathBuf), String> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    match parts[..] {
+        [prefix, dir] if prefix.starts_with('/') => Ok((prefix.to_string(), PathBuf::from(dir))),
+        _ => Err(format!("`{}` should be a path prefix followed by a directory of scripts", value)),
+    }
+}
+
+fn parse_error_page(value: &str) -> Result<(String, String), String> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    match parts[..] {
+        [status, page] if is_error_status(status) => Ok((status.to_ascii_lowercase(), page.to_string())),
+        _ => Err(format!("`{}` should be a status (like 404 or 5xx) followed by a page", value)),
+    }
+}
+
+fn is_error_status(status: &str) -> bool {
+    let status = status.to_ascii_lowercase();
+    matches!(
+        status.as_bytes(),
+        [b'4'..=b'5', b'x', b'x'] | [b'4'..=b'5', b'0'..=b'9', b'0'..=b'9']
+    )
+}
+
+fn parse_mode(value: &str) -> Result<Mode, String> {
+    match value {
+        "development" | "dev" => Ok(Mode::Development),
+        "production" | "prod" => Ok(Mode::Production),
+        _ => Err(format!("`{}` is not development or production", value)),
+    }
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "yes" | "on" => Ok(true),
+        "false" | "no" | "off" => Ok(false),
+        _ => Err(format!("`{}` is not true or false", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_settings_with_defaults() {
+        let config = Config::parse(
+            "# local dev\nthreads = 8\ntimeout = 10\ncgi = /cgi-bin scripts\ncgi_timeout = 5\nmode = development\nautoindex = on\nmetrics_path = /metrics\n\ntls_cert = c.pem\ntls_key = k.pem\nredirect_http = yes\n\
+proxy = /api 127.0.0.1:9000 127.0.0.1:9001\nproxy = /grafana localhost:3000\n\
+rate_limit = /api 0.5 10\nrate_limit_key = X-Api-Key\nrate_limit_trusted = 127.0.0.1 ::1\n",
+        )
+        .unwrap();
+
+        assert_eq!("127.0.0.1:7878", config.address);
+        assert_eq!(8, config.threads);
+        assert_eq!(Duration::from_secs(10), config.timeout);
+        assert_eq!(64, config.max_streams);
+        assert_eq!(Mode::Development, config.mode);
+        assert_eq!(vec![(String::from("/cgi-bin"), PathBuf::from("scripts"))], config.site.cgi);
+        assert_eq!(Duration::from_secs(5), config.cgi_timeout);
+        assert_eq!(PathBuf::from("public"), config.site.root);
+        assert!(config.site.autoindex);
+        assert_eq!(Some("/metrics"), config.metrics_path.as_deref());
+        assert!(config.redirect_http);
+        let tls = config.tls.unwrap();
+        assert_eq!("127.0.0.1:7879", tls.address);
+        assert_eq!(PathBuf::from("k.pem"), tls.key);
+        assert_eq!(2, config.site.proxies.len());
+        assert_eq!("/api", config.site.proxies[0].prefix);
+        assert_eq!(vec!["127.0.0.1:9000", "127.0.0.1:9001"], config.site.proxies[0].upstreams);
+        assert_eq!(
+            vec![RateLimit { prefix: String::from("/api"), per_second: 0.5, burst: 10 }],
+            config.site.rate_limits
+        );
+        assert_eq!(Some("X-Api-Key"), config.rate_limit_key.as_deref());
+        assert_eq!(vec!["127.0.0.1".parse::<IpAddr>().unwrap(), "::1".parse().unwrap()], config.rate_limit_trusted);
+    }
+
+    #[test]
+    fn reports_bad_lines() {
+        match Config::parse("address = 127.0.0.1:80\nthreads = many\n") {
+            Err(ConfigError::Invalid { line, .. }) => assert_eq!(2, line),
+            other => panic!("expected an invalid line, got {:?}", other),
+        }
+        assert!(Config::parse("colour = blue").is_err());
+        assert!(Config::parse("tls_cert = c.pem").is_err());
+        assert!(Config::parse("redirect_http = true").is_err());
+        assert!(Config::parse("proxy = /api").is_err());
+        assert!(Config::parse("rate_limit = /api 0 10").is_err());
+        assert!(Config::parse("rate_limit = /api fast").is_err());
+        assert!(Config::parse("rate_limit_trusted = proxy.internal").is_err());
+        assert!(Config::parse("[host docs.internal]\nautoindex = on\n").is_err());
+        assert!(Config::parse("[host docs.internal]\nroot = docs\nthreads = 2\n").is_err());
+        assert!(Config::parse("[site docs.internal]\n").is_err());
+        assert!(Config::parse("[host]\n").is_err());
+        assert!(Config::parse("error_page = 200 ok.html").is_err());
+        assert!(Config::parse("mode = staging").is_err());
+        assert!(Config::parse("cgi = cgi-bin").is_err());
+    }
+
+    #[test]
+    fn parses_virtual_hosts() {
+        let config = Config::parse(
+            "index = first.html\n\n[host Docs.Internal docs.local]\nroot = sites/docs\nautoindex = yes\n\
+[host wiki.internal]\nroot = sites/wiki\nerror_page = 404 missing.html\nerror_page = 5XX oops.html\n",
+        )
+        .unwrap();
+
+        assert_eq!("first.html", config.site.index);
+        assert_eq!(2, config.hosts.len());
+        assert_eq!(vec!["docs.internal", "docs.local"], config.hosts[0].names);
+        assert!(config.hosts[0].autoindex);
+        assert_eq!("index.html", config.hosts[0].index);
+        assert_eq!(PathBuf::from("sites/wiki"), config.hosts[1].root);
+        assert_eq!(
+            vec![("404", "404.html"), ("404", "missing.html"), ("5xx", "oops.html")],
+            config.hosts[1].error_pages.iter().map(|(s, p)| (s.as_str(), p.as_str())).collect::<Vec<_>>()
+        );
+        assert_eq!(Mode::Production, config.mode);
+        assert_eq!(3, config.sites().count());
+    }
+}
@@ -0,0 +1,274 @@
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::date::http_date;
+use crate::headers::is_token_char;
+use crate::request::Request;
+use crate::response::Response;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+/// A cookie to send back in a `Set-Cookie` header, built up attribute by attribute:
+///
+/// ```
+/// use std::time::Duration;
+/// use webServer::{Cookie, SameSite};
+///
+/// let cookie = Cookie::new("theme", "dark")
+///     .path("/")
+///     .max_age(Duration::from_secs(3600))
+///     .http_only(true)
+///     .same_site(SameSite::Lax);
+/// assert_eq!("theme=dark; Path=/; Max-Age=3600; HttpOnly; SameSite=Lax", cookie.to_string());
+/// ```
+///
+/// Bytes a cookie can't carry as they are (`;`, `,`, spaces, quotes, control characters and
+/// anything past ASCII in the value, anything but a token in the name) are sent
+/// percent-encoded, so no value can add attributes or break the header. `Cookies` hands
+/// values back as they were sent, it doesn't decode them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub path: Option<String>,
+    pub domain: Option<String>,
+    pub max_age: Option<Duration>,
+    pub expires: Option<SystemTime>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Cookie {
+        Cookie {
+            name: name.into(),
+            value: value.into(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// A cookie that tells the browser to forget `name` right away.
+    pub fn removal(name: impl Into<String>) -> Cookie {
+        Cookie::new(name, "")
+            .path("/")
+            .max_age(Duration::from_secs(0))
+            .expires(UNIX_EPOCH)
+    }
+
+    pub fn path(mut self, path: impl Into<String>) -> Cookie {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn domain(mut self, domain: impl Into<String>) -> Cookie {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Cookie {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn expires(mut self, expires: SystemTime) -> Cookie {
+        self.expires = Some(expires);
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Cookie {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Cookie {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+//the Display form is exactly the Set-Cookie header value
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = percent_encode(&self.name, is_token_char);
+        write!(f, "{}={}", name, percent_encode(&self.value, is_cookie_octet))?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", percent_encode(path, is_attribute_octet))?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", percent_encode(domain, is_attribute_octet))?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", http_date(expires))?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => write!(f, "; SameSite=Strict"),
+            Some(SameSite::Lax) => write!(f, "; SameSite=Lax"),
+            Some(SameSite::None) => write!(f, "; SameSite=None"),
+            None => Ok(()),
+        }
+    }
+}
+
+//RFC 6265's cookie-octet: printable ASCII but for space, `"`, `,`, `;` and `\`
+fn is_cookie_octet(b: u8) -> bool {
+    matches!(b, 0x21 | 0x23..=0x2B | 0x2D..=0x3A | 0x3C..=0x5B | 0x5D..=0x7E)
+}
+
+//an attribute value may hold anything but control characters and `;`
+fn is_attribute_octet(b: u8) -> bool {
+    (0x20..0x7F).contains(&b) && b != b';'
+}
+
+fn percent_encode(input: &str, keep: impl Fn(u8) -> bool) -> String {
+    let mut out = String::with_capacity(input.len());
+    for b in input.bytes() {
+        if keep(b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+/// The cookies a client sent, parsed from its `Cookie` header(s).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Cookies {
+    pairs: Vec<(String, String)>,
+}
+
+impl Cookies {
+    pub fn parse(header: &str) -> Cookies {
+        let mut cookies = Cookies::default();
+        cookies.add_header(header);
+        cookies
+    }
+
+    fn add_header(&mut self, header: &str) {
+        //pairs without '=' are not valid cookies, browsers skip them and so do we
+        for pair in header.split(';') {
+            if let Some(i) = pair.find('=') {
+                let name = pair[..i].trim();
+                let value = pair[i + 1..].trim();
+                //RFC 6265 allows the value to be wrapped in double quotes
+                let value = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                    &value[1..value.len() - 1]
+                } else {
+                    value
+                };
+                if !name.is_empty() {
+                    self.pairs.push((name.to_string(), value.to_string()));
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.pairs.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+impl Request {
+    pub fn cookies(&self) -> Cookies {
+        let mut cookies = Cookies::default();
+        for header in self.headers.get_all("Cookie") {
+            cookies.add_header(header);
+        }
+        cookies
+    }
+
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.cookies().get(name).map(String::from)
+    }
+}
+
+impl Response {
+    /// Adds a `Set-Cookie` header. Unlike other headers several of these can be sent.
+    pub fn add_cookie(&mut self, cookie: &Cookie) {
+        self.headers.append("Set-Cookie", cookie.to_string());
+    }
+
+    pub fn with_cookie(mut self, cookie: &Cookie) -> Response {
+        self.add_cookie(cookie);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cookie_headers() {
+        let mut request = Request::new("GET", "/");
+        request.headers.append("Cookie", "a=1; b=\"two\"; junk; =x");
+        request.headers.append("Cookie", "c=3=3");
+
+        let cookies = request.cookies();
+        assert_eq!(Some("1"), cookies.get("a"));
+        assert_eq!(Some("two"), cookies.get("b"));
+        assert_eq!(Some("3=3"), cookies.get("c"));
+        assert_eq!(None, cookies.get("junk"));
+        assert_eq!(3, cookies.iter().count());
+    }
+
+    #[test]
+    fn emits_set_cookie_with_attributes() {
+        let response = Response::new(200)
+            .with_cookie(&Cookie::new("a", "1").domain("example.com").secure(true).same_site(SameSite::Strict))
+            .with_cookie(&Cookie::removal("b"));
+
+        let set: Vec<&str> = response.headers.get_all("Set-Cookie").collect();
+        assert_eq!(
+            vec![
+                "a=1; Domain=example.com; Secure; SameSite=Strict",
+                "b=; Path=/; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT",
+            ],
+            set
+        );
+    }
+
+    #[test]
+    fn encodes_what_would_break_the_header() {
+        let cookie = Cookie::new("a b", "x; Domain=evil.example\r\nX: 1").path("/p;Secure").domain("ex\nample.com");
+        assert_eq!(
+            "a%20b=x%3B%20Domain=evil.example%0D%0AX:%201; Path=/p%3BSecure; Domain=ex%0Aample.com",
+            cookie.to_string()
+        );
+        assert_eq!("sig=abc.123%", Cookie::new("sig", "abc.123%").to_string());
+    }
+}
@@ -0,0 +1,119 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+//every failure a handler can run into ends up as one of these, carrying the status code
+//the client should see. handle_connection turns it into a Response instead of panicking.
+//the underlying error, if any, is kept as the source so development mode can show it
+#[derive(Debug)]
+pub struct HttpError {
+    status: u16,
+    message: String,
+    source: Option<Box<dyn Error + Send + Sync + 'static>>,
+}
+
+impl HttpError {
+    pub fn new(status: u16, message: impl Into<String>) -> HttpError {
+        HttpError {
+            status,
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    /// A 500 caused by `err`, e.g. `.map_err(HttpError::internal)?` on a database call.
+    pub fn internal<E: Into<Box<dyn Error + Send + Sync + 'static>>>(err: E) -> HttpError {
+        let source = err.into();
+        HttpError::new(500, source.to_string()).with_source(source)
+    }
+
+    /// Records what caused this error.
+    pub fn with_source<E: Into<Box<dyn Error + Send + Sync + 'static>>>(mut self, source: E) -> HttpError {
+        self.source = Some(source.into());
+        self
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> HttpError {
+        HttpError::new(400, message)
+    }
+
+    pub fn not_found() -> HttpError {
+        HttpError::new(404, "not found")
+    }
+
+    pub fn payload_too_large(limit: usize) -> HttpError {
+        HttpError::new(413, format!("request body is larger than {} bytes", limit))
+    }
+
+    pub fn unsupported_media_type(expected: &str) -> HttpError {
+        HttpError::new(415, format!("expected a {} body", expected))
+    }
+
+    /// What a streamed request body failed with, when the client got its framing wrong rather
+    /// than the connection failing; `ChunkedReader` wraps those in the `io::Error`.
+    pub(crate) fn from_body(err: &io::Error) -> Option<HttpError> {
+        let err = err.get_ref()?.downcast_ref::<HttpError>()?;
+        Some(HttpError::new(err.status(), err.message()))
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The messages of the errors that led to this one, the direct cause first.
+    pub fn chain(&self) -> Vec<String> {
+        let mut chain = Vec::new();
+        let mut next = self.source();
+        while let Some(err) = next {
+            chain.push(err.to_string());
+            next = err.source();
+        }
+        chain
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.status, self.message)
+    }
+}
+
+impl Error for HttpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_ref().map(|e| &**e as &(dyn Error + 'static))
+    }
+}
+
+//lets handlers use ? on fs/io calls; a missing or forbidden file is the client's problem,
+//anything else io related we didn't expect is a server error
+impl From<io::Error> for HttpError {
+    fn from(err: io::Error) -> HttpError {
+        let status = match err.kind() {
+            io::ErrorKind::NotFound => 404,
+            io::ErrorKind::PermissionDenied => 403,
+            _ => 500,
+        };
+        HttpError::new(status, err.to_string()).with_source(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_io_errors_and_keeps_the_chain() {
+        let missing = HttpError::from(io::Error::new(io::ErrorKind::NotFound, "no such page"));
+        assert_eq!(404, missing.status());
+        assert_eq!(vec!["no such page"], missing.chain());
+
+        let inner = HttpError::from(io::Error::other("disk on fire"));
+        let outer = HttpError::new(502, "could not render").with_source(inner);
+        assert_eq!(vec!["500 disk on fire", "disk on fire"], outer.chain());
+        assert_eq!(500, HttpError::internal("pool exhausted").status());
+    }
+}
@@ -0,0 +1,200 @@
+use std::sync::Arc;
+
+use crate::autoindex::escape;
+use crate::error::HttpError;
+use crate::files::FileCache;
+use crate::response::{reason_phrase, Response};
+
+/// How much an error page gives away.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// The error's message and everything that caused it, for working on the site.
+    Development,
+    /// Client errors keep their message, server errors only say what status they are; the
+    /// details go to stderr instead.
+    Production,
+}
+
+/// Renders handler errors as HTML pages, from templates under a document root.
+///
+/// A template is picked by exact status (`404`), then by class (`4xx`, `5xx`), and may use
+/// `{{status}}`, `{{reason}}`, `{{message}}` and `{{details}}`, which are filled in escaped.
+/// Without a template, or if it can't be read, a plain built-in page is used.
+pub struct ErrorPages {
+    pages: Arc<FileCache>,
+    templates: Vec<(String, String)>,
+    mode: Mode,
+}
+
+impl ErrorPages {
+    pub fn new(pages: Arc<FileCache>) -> ErrorPages {
+        ErrorPages {
+            pages,
+            templates: Vec::new(),
+            mode: Mode::Production,
+        }
+    }
+
+    /// Uses the file `page` for `status`, e.g. `"404"` or `"5xx"`. A later page for the same
+    /// status replaces an earlier one.
+    pub fn page(mut self, status: &str, page: &str) -> ErrorPages {
+        self.templates.push((status.to_ascii_lowercase(), page.to_string()));
+        self
+    }
+
+    pub fn mode(mut self, mode: Mode) -> ErrorPages {
+        self.mode = mode;
+        self
+    }
+
+    pub fn render(&self, err: &HttpError) -> Response {
+        let status = err.status();
+        let development = self.mode == Mode::Development;
+        if !development && status >= 500 {
+            eprintln!("{}", describe(err));
+        }
+
+        let reason = reason_phrase(status);
+        let message = if development || status < 500 { err.message() } else { reason };
+        let details = if development { err.chain().join("\n") } else { String::new() };
+        let values = [
+            ("status", status.to_string()),
+            ("reason", escape(reason)),
+            ("message", escape(message)),
+            ("details", escape(&details)),
+        ];
+        let fill = |template: &str| substitute(template, &values);
+
+        let page = match self.template(status) {
+            Some(template) => fill(&template),
+            None if details.is_empty() => fill("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+<title>{{status}} {{reason}}</title>\n</head>\n<body>\n<h1>{{status}} {{reason}}</h1>\n<p>{{message}}</p>\n</body>\n</html>\n"),
+            None => fill("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+<title>{{status}} {{reason}}</title>\n</head>\n<body>\n<h1>{{status}} {{reason}}</h1>\n<p>{{message}}</p>\n\
+<h2>Caused by</h2>\n<pre>{{details}}</pre>\n</body>\n</html>\n"),
+        };
+        Response::html(status, page)
+    }
+
+    fn template(&self, status: u16) -> Option<String> {
+        let exact = status.to_string();
+        let class = format!("{}xx", status / 100);
+        let name = self
+            .templates
+            .iter()
+            .rev()
+            .find(|(key, _)| *key == exact)
+            .or_else(|| self.templates.iter().rev().find(|(key, _)| *key == class))
+            .map(|(_, page)| page)?;
+        //a broken template shouldn't turn one error into another, the built-in page will do
+        let file = self.pages.read(name).ok()?;
+        Some(String::from_utf8_lossy(&file.contents).into_owned())
+    }
+}
+
+//one pass over the template, so a value that itself contains `{{details}}` is left as it is
+fn substitute(template: &str, values: &[(&str, String)]) -> String {
+    let mut page = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        page.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest[2..].find("}}").and_then(|end| {
+            let name = &rest[2..2 + end];
+            let (_, value) = values.iter().find(|(key, _)| *key == name)?;
+            Some((value, 2 + end + 2))
+        });
+        match value {
+            Some((value, length)) => {
+                page.push_str(value);
+                rest = &rest[length..];
+            }
+            None => {
+                page.push_str("{{");
+                rest = &rest[2..];
+            }
+        }
+    }
+    page.push_str(rest);
+    page
+}
+
+fn describe(err: &HttpError) -> String {
+    let mut line = err.to_string();
+    for cause in err.chain() {
+        line.push_str(": ");
+        line.push_str(&cause);
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io;
+
+    fn pages(name: &str) -> (std::path::PathBuf, Arc<FileCache>) {
+        let dir = std::env::temp_dir().join(format!("webserver-errorpages-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("404.html"), "<h1>{{status}}: {{message}}</h1>").unwrap();
+        fs::write(dir.join("50x.html"), "<h1>{{reason}}</h1><pre>{{details}}</pre>").unwrap();
+        (dir.clone(), Arc::new(FileCache::new(dir)))
+    }
+
+    fn failure() -> HttpError {
+        HttpError::new(500, "could not load <user>")
+            .with_source(io::Error::other("connection refused"))
+    }
+
+    #[test]
+    fn picks_templates_by_status_then_class() {
+        let (dir, cache) = pages("templates");
+        let errors = ErrorPages::new(cache).page("404", "404.html").page("5xx", "50x.html");
+
+        let missing = errors.render(&HttpError::new(404, "no <b>such</b> page"));
+        assert_eq!(404, missing.status);
+        assert_eq!(b"<h1>404: no &lt;b&gt;such&lt;/b&gt; page</h1>", missing.body.as_bytes());
+
+        let broken = errors.render(&failure());
+        assert_eq!(b"<h1>Internal Server Error</h1><pre></pre>", broken.body.as_bytes());
+
+        //no template for 4xx in general, so the built-in page
+        let forbidden = String::from_utf8(errors.render(&HttpError::new(403, "keep out")).body.as_bytes().to_vec()).unwrap();
+        assert!(forbidden.contains("<title>403 Forbidden</title>"));
+        assert!(forbidden.contains("<p>keep out</p>"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_development_mode_shows_the_chain() {
+        let (dir, cache) = pages("mode");
+        let production = ErrorPages::new(Arc::clone(&cache));
+        let page = String::from_utf8(production.render(&failure()).body.as_bytes().to_vec()).unwrap();
+        assert!(!page.contains("load"));
+        assert!(!page.contains("refused"));
+
+        let development = ErrorPages::new(Arc::clone(&cache)).mode(Mode::Development);
+        let page = String::from_utf8(development.render(&failure()).body.as_bytes().to_vec()).unwrap();
+        assert!(page.contains("could not load &lt;user&gt;"));
+        assert!(page.contains("<pre>connection refused</pre>"));
+
+        let templated = ErrorPages::new(cache).page("5xx", "50x.html").mode(Mode::Development);
+        assert_eq!(
+            b"<h1>Internal Server Error</h1><pre>connection refused</pre>",
+            templated.render(&failure()).body.as_bytes()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn placeholders_in_messages_stay_as_they_are() {
+        let (dir, cache) = pages("placeholders");
+        let errors = ErrorPages::new(cache).page("404", "404.html");
+        let page = errors.render(&HttpError::new(404, "no {{status}} {{details}} here"));
+        assert_eq!(b"<h1>404: no {{status}} {{details}} here</h1>", page.body.as_bytes());
+        let values = [("x", String::from("a")), ("y", String::from("{{x}}"))];
+        assert_eq!("a {{x}} {{z}} {{", substitute("{{x}} {{y}} {{z}} {{", &values));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
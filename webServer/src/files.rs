@@ -0,0 +1,477 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::autoindex;
+use crate::date::{http_date, parse_http_date};
+use crate::error::HttpError;
+use crate::headers::Headers;
+use crate::request::Request;
+use crate::response::Response;
+use crate::router::Handler;
+use crate::url::encode_path;
+
+/// A file as it was read into the cache, with the headers describing it worked out once.
+#[derive(Debug)]
+pub struct CachedFile {
+    /// Shared with the responses serving it, a hit doesn't copy the file.
+    pub contents: Arc<[u8]>,
+    /// `Content-Type`, `Last-Modified` and `ETag`.
+    pub headers: Headers,
+    pub modified: SystemTime,
+}
+
+impl CachedFile {
+    fn new(path: &Path, contents: Vec<u8>, modified: SystemTime) -> CachedFile {
+        CachedFile {
+            headers: describe(path, modified, contents.len() as u64),
+            contents: contents.into(),
+            modified,
+        }
+    }
+
+    pub fn response(&self, status: u16) -> Response {
+        let mut response = Response::new(status).with_shared(Arc::clone(&self.contents));
+        response.headers = self.headers.clone();
+        response
+    }
+}
+
+//the headers a file is served with, the same whether it comes from memory or straight from disk
+fn describe(path: &Path, modified: SystemTime, len: u64) -> Headers {
+    let mut headers = Headers::new();
+    headers.append("Content-Type", content_type(path));
+    headers.append("Last-Modified", http_date(modified));
+    let stamp = modified.duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    headers.append("ETag", format!("\"{:x}-{:x}\"", stamp, len));
+    headers
+}
+
+/// Hit and miss counts since the cache was created.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub entries: usize,
+    pub bytes: usize,
+    pub capacity: usize,
+}
+
+//the Prometheus text format, so the numbers can be scraped as they are
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "file_cache_hits_total {}", self.hits)?;
+        writeln!(f, "file_cache_misses_total {}", self.misses)?;
+        writeln!(f, "file_cache_evictions_total {}", self.evictions)?;
+        writeln!(f, "file_cache_entries {}", self.entries)?;
+        writeln!(f, "file_cache_bytes {}", self.bytes)?;
+        writeln!(f, "file_cache_capacity_bytes {}", self.capacity)
+    }
+}
+
+struct Entry {
+    file: Arc<CachedFile>,
+    len: u64,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<PathBuf, Entry>,
+    //last use -> path, oldest first; the tick only goes up so every use has its own key
+    order: BTreeMap<u64, PathBuf>,
+    tick: u64,
+    bytes: usize,
+}
+
+/// Files under a document root, kept in memory up to `capacity` bytes, least recently used
+/// going first when there is no room.
+///
+/// Each read checks the file's modification time and size, so an edited file is read again
+/// rather than served stale. Files bigger than the whole cache are read every time by `read`,
+/// and streamed from disk by `response`.
+pub struct FileCache {
+    root: PathBuf,
+    capacity: usize,
+    lru: Mutex<Lru>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl FileCache {
+    pub fn new(root: impl Into<PathBuf>) -> FileCache {
+        FileCache {
+            root: root.into(),
+            capacity: 16 * 1024 * 1024,
+            lru: Mutex::new(Lru::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// The most bytes of file contents to hold on to, 16 MiB by default.
+    pub fn with_capacity(mut self, bytes: usize) -> FileCache {
+        self.capacity = bytes;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Where `path` is on disk. A leading `/` is allowed so request paths can be passed as they
+    /// are; `..` and the like are refused rather than leave the root.
+    pub fn resolve(&self, path: &str) -> io::Result<PathBuf> {
+        let relative = Path::new(path.trim_start_matches('/'));
+        if !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("{} is outside the document root", path)));
+        }
+        Ok(self.root.join(relative))
+    }
+
+    /// The file at `path`, relative to the root, as `resolve` finds it.
+    pub fn read(&self, path: &str) -> io::Result<Arc<CachedFile>> {
+        let full = self.resolve(path)?;
+        let metadata = fs::metadata(&full)?;
+        if !metadata.is_file() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} is not a file", path)));
+        }
+        let modified = metadata.modified()?;
+
+        {
+            let mut lru = self.lru.lock().unwrap();
+            let lru = &mut *lru;
+            if let Some(entry) = lru.entries.get_mut(&full) {
+                if entry.file.modified == modified && entry.len == metadata.len() {
+                    lru.order.remove(&entry.last_used);
+                    lru.tick += 1;
+                    entry.last_used = lru.tick;
+                    lru.order.insert(lru.tick, full.clone());
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    return Ok(Arc::clone(&entry.file));
+                }
+            }
+        }
+
+        //read without holding the lock, other workers keep being served from memory meanwhile
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let file = Arc::new(CachedFile::new(&full, fs::read(&full)?, modified));
+        if file.contents.len() <= self.capacity {
+            self.insert(full, Arc::clone(&file), metadata.len());
+        }
+        Ok(file)
+    }
+
+    /// A 200 response with the file at `path`, from memory if it fits in the cache; a bigger
+    /// one is sent straight from disk rather than read whole for every request.
+    pub fn response(&self, path: &str) -> io::Result<Response> {
+        let full = self.resolve(path)?;
+        let metadata = fs::metadata(&full)?;
+        if !metadata.is_file() || metadata.len() <= self.capacity as u64 {
+            return Ok(self.read(path)?.response(200));
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let len = metadata.len();
+        //a file that grows meanwhile is cut at the length we announced
+        let file = fs::File::open(&full)?.take(len);
+        let mut response = Response::new(200).with_stream(file, Some(len));
+        response.headers = describe(&full, metadata.modified()?, len);
+        Ok(response)
+    }
+
+    fn insert(&self, path: PathBuf, file: Arc<CachedFile>, len: u64) {
+        let mut lru = self.lru.lock().unwrap();
+        if let Some(old) = lru.entries.remove(&path) {
+            lru.order.remove(&old.last_used);
+            lru.bytes -= old.file.contents.len();
+        }
+        while lru.bytes + file.contents.len() > self.capacity {
+            let oldest = match lru.order.keys().next() {
+                Some(&tick) => lru.order.remove(&tick).unwrap(),
+                None => break,
+            };
+            if let Some(evicted) = lru.entries.remove(&oldest) {
+                lru.bytes -= evicted.file.contents.len();
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        lru.tick += 1;
+        let tick = lru.tick;
+        lru.bytes += file.contents.len();
+        lru.order.insert(tick, path.clone());
+        lru.entries.insert(path, Entry { file, len, last_used: tick });
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let lru = self.lru.lock().unwrap();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            entries: lru.entries.len(),
+            bytes: lru.bytes,
+            capacity: self.capacity,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lru.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Serves the files under a `FileCache`'s root for GET and HEAD, by request path.
+///
+/// A directory is answered with its index file; without one, with a listing if `autoindex`
+/// is on, otherwise with a 404 like a missing file. Give the router `ErrorPages` for a nicer
+/// page than the plain text one.
+///
+/// Names starting with a dot (`.git`, `.env`) are 404s, as they are left out of listings.
+/// A client whose `If-None-Match` or `If-Modified-Since` shows it has the file already gets a
+/// 304 without it.
+pub struct StaticFiles {
+    cache: Arc<FileCache>,
+    index: String,
+    autoindex: bool,
+}
+
+impl StaticFiles {
+    pub fn new(cache: Arc<FileCache>) -> StaticFiles {
+        StaticFiles {
+            cache,
+            index: String::from("index.html"),
+            autoindex: false,
+        }
+    }
+
+    pub fn index(mut self, name: impl Into<String>) -> StaticFiles {
+        self.index = name.into();
+        self
+    }
+
+    pub fn autoindex(mut self, on: bool) -> StaticFiles {
+        self.autoindex = on;
+        self
+    }
+}
+
+impl Handler for StaticFiles {
+    fn handle(&self, request: &Request) -> Result<Response, HttpError> {
+        if request.method != "GET" && request.method != "HEAD" {
+            return Err(HttpError::new(405, format!("{} is not allowed here", request.method)));
+        }
+        if request.path.split('/').any(|segment| segment.starts_with('.')) {
+            return Err(HttpError::not_found());
+        }
+        //a missing file is a 404 and one outside the root a 403, by way of the io::Error
+        let full = self.cache.resolve(&request.path)?;
+        if !fs::metadata(&full)?.is_dir() {
+            return Ok(not_modified(request, self.cache.response(&request.path)?));
+        }
+        //relative links in the page only work from behind the slash
+        if !request.path.ends_with('/') {
+            return Ok(Response::new(301).with_header("Location", encode_path(&request.path) + "/"));
+        }
+        let index = format!("{}{}", request.path, self.index);
+        match self.cache.response(&index) {
+            Ok(page) => Ok(not_modified(request, page)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound && self.autoindex => {
+                Ok(autoindex::listing(&full, &request.path, request)?)
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Err(HttpError::not_found()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+//a 304 with the validators and no body when the client's copy is current. If-None-Match wins
+//over If-Modified-Since when both are sent (RFC 9110 13.2.2)
+fn not_modified(request: &Request, response: Response) -> Response {
+    let current = match (request.header("If-None-Match"), response.headers.get("ETag")) {
+        (Some(tags), Some(etag)) => tags
+            .split(',')
+            .map(|tag| tag.trim())
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag),
+        (Some(_), None) => false,
+        (None, _) => {
+            let since = request.header("If-Modified-Since").and_then(parse_http_date);
+            let modified = response.headers.get("Last-Modified").and_then(parse_http_date);
+            matches!((since, modified), (Some(since), Some(modified)) if modified <= since)
+        }
+    };
+    if !current {
+        return response;
+    }
+    let mut headers = response.headers;
+    headers.remove("Content-Type");
+    let mut response = Response::new(304);
+    response.headers = headers;
+    response
+}
+
+/// A media type for `path`, going by its extension.
+pub(crate) fn content_type(path: &Path) -> &'static str {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff2" => "font/woff2",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::Body;
+
+    fn root(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("webserver-files-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn serves_from_memory_until_the_file_changes() {
+        let dir = root("mtime");
+        fs::write(dir.join("first.html"), "<h1>Hello!</h1>").unwrap();
+
+        let cache = FileCache::new(&dir);
+        let file = cache.read("/first.html").unwrap();
+        assert_eq!(b"<h1>Hello!</h1>", &file.contents[..]);
+        assert_eq!(Some("text/html; charset=utf-8"), file.headers.get("Content-Type"));
+        assert!(Arc::ptr_eq(&file, &cache.read("first.html").unwrap()));
+        //responses share the cached bytes rather than copying them
+        assert_eq!(file.contents.as_ptr(), file.response(200).body.as_bytes().as_ptr());
+
+        fs::write(dir.join("first.html"), "<h1>Hello again!</h1>").unwrap();
+        assert_eq!(b"<h1>Hello again!</h1>", &cache.read("first.html").unwrap().contents[..]);
+        let stats = cache.stats();
+        assert_eq!((1, 2, 1), (stats.hits, stats.misses, stats.entries));
+
+        assert_eq!(io::ErrorKind::NotFound, cache.read("missing.html").unwrap_err().kind());
+        assert_eq!(io::ErrorKind::PermissionDenied, cache.read("../etc/passwd").unwrap_err().kind());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used() {
+        let dir = root("lru");
+        for name in &["a", "b", "c"] {
+            fs::write(dir.join(name), [0u8; 40]).unwrap();
+        }
+        fs::write(dir.join("big"), [0u8; 200]).unwrap();
+
+        let cache = FileCache::new(&dir).with_capacity(100);
+        cache.read("a").unwrap();
+        cache.read("b").unwrap();
+        cache.read("a").unwrap();
+        cache.read("c").unwrap();
+        //b was used least recently, so it made room for c
+        let stats = cache.stats();
+        assert_eq!((2, 80, 1), (stats.entries, stats.bytes, stats.evictions));
+        cache.read("a").unwrap();
+        assert_eq!(2, cache.stats().hits);
+
+        //too big to keep, but still served, and streamed when it's for a response
+        assert_eq!(200, cache.read("big").unwrap().contents.len());
+        assert_eq!(2, cache.len());
+        let response = cache.response("big").unwrap();
+        assert!(matches!(response.body, Body::Stream { length: Some(200), .. }));
+        assert!(response.headers.get("ETag").is_some());
+        assert_eq!(2, cache.len());
+        assert!(cache.stats().to_string().contains("file_cache_misses_total 5\n"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn serves_files_indexes_and_listings() {
+        let dir = root("static");
+        fs::create_dir_all(dir.join("docs")).unwrap();
+        fs::create_dir_all(dir.join("site")).unwrap();
+        fs::write(dir.join("docs").join("a.txt"), "a").unwrap();
+        fs::write(dir.join("site").join("index.html"), "home").unwrap();
+
+        let cache = Arc::new(FileCache::new(&dir));
+        let files = StaticFiles::new(Arc::clone(&cache));
+        let get = |files: &StaticFiles, path: &str| files.handle(&Request::new("GET", path)).unwrap();
+        let status = |files: &StaticFiles, path: &str| files.handle(&Request::new("GET", path)).unwrap_err().status();
+
+        assert_eq!(b"a", get(&files, "/docs/a.txt").body.as_bytes());
+        assert_eq!(Some("text/plain; charset=utf-8"), get(&files, "/docs/a.txt").headers.get("Content-Type"));
+        assert_eq!(b"home", get(&files, "/site/").body.as_bytes());
+        assert_eq!(Some("/site/"), get(&files, "/site").headers.get("Location"));
+        //no index and no listing: same as a missing file
+        assert_eq!(404, status(&files, "/docs/"));
+        assert_eq!(404, status(&files, "/nope.html"));
+
+        let files = files.autoindex(true);
+        let listing = get(&files, "/docs/");
+        assert_eq!(200, listing.status);
+        assert!(String::from_utf8_lossy(listing.body.as_bytes()).contains("<a href=\"a.txt\">a.txt</a>"));
+        assert_eq!(405, files.handle(&Request::new("POST", "/docs/a.txt")).unwrap_err().status());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn answers_conditional_requests_with_304() {
+        let dir = root("conditional");
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        let files = StaticFiles::new(Arc::new(FileCache::new(&dir)));
+        let get = |header: &str, value: &str| {
+            let mut request = Request::new("GET", "/a.txt");
+            request.headers.append(header, value);
+            files.handle(&request).unwrap()
+        };
+
+        let first = files.handle(&Request::new("GET", "/a.txt")).unwrap();
+        let etag = first.headers.get("ETag").unwrap();
+        let modified = first.headers.get("Last-Modified").unwrap();
+        let cached = get("If-None-Match", &format!("\"x\", W/{}", etag));
+        assert_eq!(304, cached.status);
+        assert!(cached.body.as_bytes().is_empty());
+        assert_eq!(Some(etag), cached.headers.get("ETag"));
+        assert_eq!(304, get("If-Modified-Since", modified).status);
+
+        assert_eq!(200, get("If-None-Match", "\"other\"").status);
+        assert_eq!(200, get("If-Modified-Since", "Thu, 01 Jan 1970 00:00:00 GMT").status);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hides_dotfiles_like_listings_do() {
+        let dir = root("dotfiles");
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::write(dir.join(".git").join("config"), "secret").unwrap();
+        fs::write(dir.join(".env"), "secret").unwrap();
+        let files = StaticFiles::new(Arc::new(FileCache::new(&dir))).autoindex(true);
+        for path in &["/.env", "/.git/config", "/.git/"] {
+            assert_eq!(404, files.handle(&Request::new("GET", path)).unwrap_err().status(), "{}", path);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,8 +1,50 @@
+//the package is called webServer, which is fine for a binary name but trips the snake case lint for the library
+#![allow(non_snake_case)]
+
 use std::thread;
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::sync::Mutex;
 
+mod autoindex;
+mod body;
+pub mod cgi;
+mod config;
+mod cookie;
+pub mod date;
+mod error;
+mod errorpages;
+mod files;
+mod headers;
+mod request;
+mod response;
+mod router;
+mod server;
+pub mod proxy;
+mod ratelimit;
+pub mod reload;
+mod session;
+pub mod sse;
+pub mod testing;
+pub mod tls;
+pub mod url;
+pub mod websocket;
+
+pub use body::{Form, Multipart, UploadedFile};
+pub use config::{Config, ConfigError, ProxyRoute, RateLimit, Site, TlsSettings};
+pub use cookie::{Cookie, Cookies, SameSite};
+pub use error::HttpError;
+pub use errorpages::{ErrorPages, Mode};
+pub use files::{CacheStats, CachedFile, FileCache, StaticFiles};
+pub use headers::Headers;
+pub use ratelimit::RateLimiter;
+pub use request::{Limits, Request};
+pub use response::{Body, Response, Upgrade};
+pub use router::{Handler, Router};
+pub use server::{handle_connection, Connection, Server};
+pub use session::{Session, SessionStore};
+pub use url::Params;
+
 //the closures we’re passing to the thread pool will handle the connection and not return anything, so T will be the unit type () for JoinHandle
 pub struct ThreadPool {
     workers : Vec<Worker>,
@@ -0,0 +1,543 @@
+use std::io::prelude::*;
+use std::io::{self, BufReader, BufWriter};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::error::HttpError;
+use crate::headers::Headers;
+use crate::request::{read_line, ChunkedReader, Framing, Limits, Request};
+use crate::response::Response;
+use crate::router::Handler;
+
+//headers that describe a single connection, not the message; a proxy must not pass them on,
+//nor the ones the Connection header lists (RFC 9110 7.6.1)
+const HOP_BY_HOP: [&str; 8] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+struct Upstream {
+    address: String,
+    failures: usize,
+    down_until: Option<Instant>,
+}
+
+/// Forwards requests to a set of upstream HTTP servers, taking turns between them.
+///
+/// Health checks are passive: an upstream we fail to connect to `max_failures` times in a row
+/// is left out for `fail_timeout`, then tried again with the next request that comes its way.
+pub struct ReverseProxy {
+    upstreams: Mutex<Vec<Upstream>>,
+    next: AtomicUsize,
+    max_failures: usize,
+    fail_timeout: Duration,
+    timeout: Duration,
+}
+
+impl ReverseProxy {
+    /// # Panics
+    ///
+    /// Panics if `upstreams` is empty.
+    pub fn new<S: AsRef<str>>(upstreams: &[S]) -> ReverseProxy {
+        assert!(!upstreams.is_empty(), "a proxy needs at least one upstream");
+        ReverseProxy {
+            upstreams: Mutex::new(
+                upstreams
+                    .iter()
+                    .map(|address| Upstream {
+                        address: address.as_ref().to_string(),
+                        failures: 0,
+                        down_until: None,
+                    })
+                    .collect(),
+            ),
+            next: AtomicUsize::new(0),
+            max_failures: 1,
+            fail_timeout: Duration::from_secs(10),
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    pub fn max_failures(mut self, failures: usize) -> ReverseProxy {
+        self.max_failures = failures.max(1);
+        self
+    }
+
+    pub fn fail_timeout(mut self, timeout: Duration) -> ReverseProxy {
+        self.fail_timeout = timeout;
+        self
+    }
+
+    /// Connect and read timeout for each upstream connection.
+    pub fn timeout(mut self, timeout: Duration) -> ReverseProxy {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Upstreams currently considered healthy.
+    pub fn healthy(&self) -> Vec<String> {
+        let now = Instant::now();
+        self.upstreams
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|u| u.down_until.is_none_or(|until| until <= now))
+            .map(|u| u.address.clone())
+            .collect()
+    }
+
+    //the healthy upstreams, starting from the next one in the rotation. If all of them are down
+    //we try them all anyway: failing a request for sure is worse than retrying one early
+    fn candidates(&self) -> Vec<String> {
+        let upstreams = self.upstreams.lock().unwrap();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let rotation: Vec<&Upstream> = (0..upstreams.len())
+            .map(|i| &upstreams[(start + i) % upstreams.len()])
+            .collect();
+
+        let healthy: Vec<String> = rotation
+            .iter()
+            .filter(|u| u.down_until.is_none_or(|until| until <= now))
+            .map(|u| u.address.clone())
+            .collect();
+        if healthy.is_empty() {
+            rotation.iter().map(|u| u.address.clone()).collect()
+        } else {
+            healthy
+        }
+    }
+
+    fn record(&self, address: &str, ok: bool) {
+        let mut upstreams = self.upstreams.lock().unwrap();
+        if let Some(upstream) = upstreams.iter_mut().find(|u| u.address == address) {
+            if ok {
+                upstream.failures = 0;
+                upstream.down_until = None;
+            } else {
+                upstream.failures += 1;
+                if upstream.failures >= self.max_failures {
+                    upstream.down_until = Some(Instant::now() + self.fail_timeout);
+                }
+            }
+        }
+    }
+
+    fn connect(&self, address: &str) -> io::Result<TcpStream> {
+        let addrs: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve", address));
+        for addr in addrs {
+            match TcpStream::connect_timeout(&addr, self.timeout) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(self.timeout))?;
+                    stream.set_write_timeout(Some(self.timeout))?;
+                    return Ok(stream);
+                }
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err)
+    }
+
+    //the body goes out with the same length, or in chunks of our own when it came chunked and its
+    //length isn't known until it ends
+    fn forward(
+        &self,
+        address: &str,
+        upstream: TcpStream,
+        request: &Request,
+        body: &mut dyn Read,
+        length: Option<usize>,
+    ) -> io::Result<Response> {
+        let skip = hop_by_hop(&request.headers);
+        let mut head = format!("{} {} HTTP/1.1\r\n", request.method, request.target);
+        for (name, value) in request.headers.iter() {
+            //Expect goes too: the body is sent right behind the head, nobody waits for a 100
+            if is_listed(&skip, name)
+                || ["Host", "Content-Length", "Expect", "X-Forwarded-For", "X-Forwarded-Host"]
+                    .iter()
+                    .any(|h| name.eq_ignore_ascii_case(h))
+            {
+                continue;
+            }
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str(&format!("Host: {}\r\n", address));
+        if let Some(host) = request.header("Host") {
+            head.push_str(&format!("X-Forwarded-Host: {}\r\n", host));
+        }
+        //append the client to any chain of proxies already in front of us
+        let forwarded_for = request.headers.get_all("X-Forwarded-For").collect::<Vec<_>>().join(", ");
+        match (forwarded_for.is_empty(), request.peer) {
+            (true, Some(peer)) => head.push_str(&format!("X-Forwarded-For: {}\r\n", peer.ip())),
+            (false, Some(peer)) => head.push_str(&format!("X-Forwarded-For: {}, {}\r\n", forwarded_for, peer.ip())),
+            (false, None) => head.push_str(&format!("X-Forwarded-For: {}\r\n", forwarded_for)),
+            (true, None) => {}
+        }
+        match length {
+            Some(length) if length > 0 || request.headers.contains("Content-Length") => {
+                head.push_str(&format!("Content-Length: {}\r\n", length));
+            }
+            Some(_) => {}
+            None => head.push_str("Transfer-Encoding: chunked\r\n"),
+        }
+        head.push_str("Connection: close\r\n\r\n");
+
+        let mut writer = BufWriter::new(upstream.try_clone()?);
+        writer.write_all(head.as_bytes())?;
+        match length {
+            Some(length) => {
+                let copied = io::copy(&mut body.take(length as u64), &mut writer)?;
+                if copied < length as u64 {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the client's body ended early"));
+                }
+            }
+            None => copy_chunked(body, &mut writer)?,
+        }
+        writer.flush()?;
+
+        let mut reader = BufReader::new(upstream);
+        let (status, headers) = read_response_head(&mut reader)?;
+
+        let skip = hop_by_hop(&headers);
+        let mut response = Response::new(status);
+        for (name, value) in headers.iter() {
+            if !is_listed(&skip, name) && !name.eq_ignore_ascii_case("Content-Length") {
+                response.headers.append(name, value);
+            }
+        }
+        let chunked = headers
+            .get("Transfer-Encoding")
+            .is_some_and(|v| v.to_ascii_lowercase().contains("chunked"));
+        let length = headers.get("Content-Length").and_then(|v| v.trim().parse::<u64>().ok());
+
+        //the body is copied to the client as it arrives instead of being collected first
+        Ok(if request.method == "HEAD" {
+            //no body, but the length the client would get with a GET
+            response.with_stream(io::empty(), length)
+        } else if status == 204 || status == 304 {
+            response.with_body(Vec::new())
+        } else if chunked {
+            response.with_stream(ChunkedReader::new(reader), None)
+        } else if let Some(length) = length {
+            response.with_stream(reader.take(length), Some(length))
+        } else {
+            response.with_stream(reader, None)
+        })
+    }
+}
+
+impl Handler for ReverseProxy {
+    fn handle(&self, request: &Request) -> Result<Response, HttpError> {
+        self.proxy(request, &mut &request.body[..], Some(request.body.len()))
+    }
+
+    fn streams_body(&self) -> bool {
+        true
+    }
+
+    fn handle_stream(&self, request: &Request, body: &mut dyn Read) -> Result<Response, HttpError> {
+        let length = match request.framing()? {
+            Framing::Chunked => None,
+            Framing::Length(length) => Some(length),
+        };
+        self.proxy(request, body, length)
+    }
+}
+
+impl ReverseProxy {
+    fn proxy(&self, request: &Request, body: &mut dyn Read, length: Option<usize>) -> Result<Response, HttpError> {
+        for address in self.candidates() {
+            //only a failed connect is retried on the next upstream: once the request went out
+            //it may have had side effects, so sending it again is not ours to decide
+            let upstream = match self.connect(&address) {
+                Ok(upstream) => upstream,
+                Err(e) => {
+                    eprintln!("Upstream {} unreachable: {}", address, e);
+                    self.record(&address, false);
+                    continue;
+                }
+            };
+            return match self.forward(&address, upstream, request, body, length) {
+                Ok(response) => {
+                    self.record(&address, true);
+                    Ok(response)
+                }
+                //a client body that broke off or was badly framed is the client's failure
+                Err(e) => match HttpError::from_body(&e) {
+                    Some(err) => Err(err),
+                    None => {
+                        self.record(&address, false);
+                        let status = if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut {
+                            504
+                        } else {
+                            502
+                        };
+                        Err(HttpError::new(status, format!("upstream {} failed: {}", address, e)))
+                    }
+                },
+            };
+        }
+        Err(HttpError::new(502, "no upstream could be reached"))
+    }
+}
+
+//the hop-by-hop headers of a message: the standard ones and those its Connection header names
+fn hop_by_hop(headers: &Headers) -> Vec<String> {
+    let mut names: Vec<String> = HOP_BY_HOP.iter().map(|h| h.to_string()).collect();
+    for value in headers.get_all("Connection") {
+        names.extend(value.split(',').map(str::trim).filter(|n| !n.is_empty()).map(String::from));
+    }
+    names
+}
+
+fn is_listed(names: &[String], name: &str) -> bool {
+    names.iter().any(|n| name.eq_ignore_ascii_case(n))
+}
+
+fn copy_chunked(body: &mut dyn Read, writer: &mut dyn Write) -> io::Result<()> {
+    let mut buf = [0; 8 * 1024];
+    loop {
+        let read = match body.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        write!(writer, "{:x}\r\n", read)?;
+        writer.write_all(&buf[..read])?;
+        writer.write_all(b"\r\n")?;
+    }
+    writer.write_all(b"0\r\n\r\n")
+}
+
+/// Reads a response's status and headers, past any interim 1xx responses, held to the limits
+/// we hold requests to.
+pub(crate) fn read_response_head<R: BufRead>(reader: &mut R) -> io::Result<(u16, Headers)> {
+    let bad = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let limits = Limits::default();
+    let next_line = |reader: &mut R| {
+        read_line(reader, limits.max_line).map_err(|e| match e.status() {
+            431 => bad("upstream sent a response head line that is too long"),
+            _ => bad("upstream sent an incomplete or malformed response head"),
+        })
+    };
+
+    loop {
+        let status_line = next_line(reader)?;
+        let status = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse::<u16>().ok())
+            .filter(|_| status_line.starts_with("HTTP/1."))
+            .ok_or_else(|| bad("upstream sent a malformed status line"))?;
+
+        let mut headers = Headers::new();
+        loop {
+            let line = next_line(reader)?;
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() == limits.max_headers {
+                return Err(bad("upstream sent too many headers"));
+            }
+            let i = line.find(':').ok_or_else(|| bad("upstream sent a malformed header"))?;
+            headers.append(line[..i].trim(), line[i + 1..].trim());
+        }
+        //100 Continue, 103 Early Hints and the like come ahead of the real response; a 101
+        //is the real one, the connection has changed hands
+        if !(100..200).contains(&status) || status == 101 {
+            return Ok((status, headers));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::Limits;
+    use crate::response::Body;
+    use crate::router::Router;
+    use crate::testing::TestClient;
+    use std::net::TcpListener;
+    use std::thread;
+
+    //a one-shot upstream: hands back the request (head and body) it got and answers with `reply`
+    fn upstream(reply: &'static str, connections: usize) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let mut heads = Vec::new();
+            for stream in listener.incoming().take(connections) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut head = String::new();
+                while !head.ends_with("\r\n\r\n") {
+                    reader.read_line(&mut head).unwrap();
+                }
+                if head.contains("Transfer-Encoding: chunked") {
+                    while !head.ends_with("\r\n0\r\n\r\n") {
+                        reader.read_line(&mut head).unwrap();
+                    }
+                } else if let Some(i) = head.find("Content-Length: ") {
+                    let length: usize = head[i + 16..].lines().next().unwrap().parse().unwrap();
+                    let mut body = vec![0; length];
+                    reader.read_exact(&mut body).unwrap();
+                    head.push_str(std::str::from_utf8(&body).unwrap());
+                }
+                stream.write_all(reply.as_bytes()).unwrap();
+                heads.push(head);
+            }
+            heads
+        });
+        (address, handle)
+    }
+
+    fn body_of(mut response: Response) -> String {
+        let mut body = String::new();
+        match &mut response.body {
+            Body::Stream { reader, .. } => {
+                reader.read_to_string(&mut body).unwrap();
+            }
+            other => body.push_str(std::str::from_utf8(other.as_bytes()).unwrap()),
+        }
+        body
+    }
+
+    #[test]
+    fn rewrites_headers_and_streams_the_reply() {
+        let (address, upstream) = upstream("HTTP/1.1 201 Created\r\nContent-Length: 5\r\nX-Up: 1\r\n\r\nhello", 1);
+        let proxy = ReverseProxy::new(std::slice::from_ref(&address));
+
+        let mut request = Request::new("POST", "/api/items?x=1");
+        request.headers.append("Host", "dashboard.local");
+        request.headers.append("X-Forwarded-For", "10.0.0.1");
+        request.headers.append("Connection", "keep-alive, X-Hop");
+        request.headers.append("X-Hop", "secret");
+        request.body = b"item".to_vec();
+        request.peer = Some("192.168.1.5:50000".parse().unwrap());
+        let response = proxy.handle(&request).unwrap();
+
+        assert_eq!(201, response.status);
+        assert_eq!(Some("1"), response.headers.get("X-Up"));
+        assert_eq!("hello", body_of(response));
+
+        let head = &upstream.join().unwrap()[0];
+        assert!(head.starts_with("POST /api/items?x=1 HTTP/1.1\r\n"));
+        assert!(head.contains(&format!("Host: {}\r\n", address)));
+        assert!(head.contains("X-Forwarded-Host: dashboard.local\r\n"));
+        assert!(head.contains("X-Forwarded-For: 10.0.0.1, 192.168.1.5\r\n"));
+        assert!(!head.contains("keep-alive"));
+        assert!(!head.contains("X-Hop"));
+        assert!(head.ends_with("Content-Length: 4\r\nConnection: close\r\n\r\nitem"));
+    }
+
+    #[test]
+    fn streams_chunked_bodies_upstream() {
+        let (address, upstream) = upstream("HTTP/1.1 204 No Content\r\n\r\n", 1);
+        let proxy = ReverseProxy::new(&[address]);
+        assert!(proxy.streams_body());
+
+        let mut request = Request::new("PUT", "/upload");
+        request.headers.append("Transfer-Encoding", "chunked");
+        let mut body: &[u8] = b"a whole lot of data";
+        assert_eq!(204, proxy.handle_stream(&request, &mut body).unwrap().status);
+
+        let head = &upstream.join().unwrap()[0];
+        assert!(head.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(head.ends_with("\r\n\r\n13\r\na whole lot of data\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn streamed_bodies_skip_the_buffering_limit() {
+        let (address, upstream) = upstream("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok", 1);
+        let client = TestClient::new(Router::new().mount("/", ReverseProxy::new(&[address])))
+            .with_limits(Limits { max_body: 4, ..Limits::default() });
+
+        let response = client.raw(b"POST /big HTTP/1.1\r\nContent-Length: 10\r\n\r\n0123456789").unwrap();
+        assert_eq!(b"ok", response.body.as_bytes());
+        assert!(upstream.join().unwrap()[0].ends_with("\r\n\r\n0123456789"));
+    }
+
+    #[test]
+    fn decodes_chunked_replies() {
+        let reply = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nwiki\r\n5;x=y\r\npedia\r\n0\r\nTrailer: t\r\n\r\n";
+        let (address, _) = upstream(reply, 1);
+        let response = ReverseProxy::new(&[address]).handle(&Request::new("GET", "/")).unwrap();
+
+        assert_eq!(None, response.headers.get("Transfer-Encoding"));
+        assert_eq!("wikipedia", body_of(response));
+    }
+
+    #[test]
+    fn round_robins_and_skips_dead_upstreams() {
+        let (a, a_handle) = upstream("HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\na", 2);
+        let (b, b_handle) = upstream("HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\nb", 2);
+        //bound and dropped again, so nothing listens there any more
+        let dead = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+
+        let proxy = ReverseProxy::new(&[a.clone(), dead.clone(), b.clone()]);
+        let bodies: Vec<String> = (0..4)
+            .map(|_| body_of(proxy.handle(&Request::new("GET", "/")).unwrap()))
+            .collect();
+
+        assert_eq!(vec!["a", "b", "b", "a"], bodies);
+        assert_eq!(vec![a, b], proxy.healthy());
+        a_handle.join().unwrap();
+        b_handle.join().unwrap();
+    }
+
+    #[test]
+    fn skips_interim_responses() {
+        let reply = "HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 103 Early Hints\r\nLink: </a.css>\r\n\r\n\
+HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+        let (address, upstream) = upstream(reply, 1);
+        let mut request = Request::new("POST", "/");
+        request.headers.append("Expect", "100-continue");
+        request.body = b"item".to_vec();
+        let response = ReverseProxy::new(&[address]).handle(&request).unwrap();
+
+        assert_eq!(200, response.status);
+        assert_eq!(None, response.headers.get("Link"));
+        assert_eq!("ok", body_of(response));
+        assert!(!upstream.join().unwrap()[0].contains("Expect"));
+    }
+
+    #[test]
+    fn endless_upstream_heads_are_502() {
+        let long = Box::leak(format!("HTTP/1.1 200 OK\r\nX-Big: {}\r\n\r\n", "a".repeat(64 * 1024)).into_boxed_str());
+        let (address, _) = upstream(long, 1);
+        assert_eq!(502, ReverseProxy::new(&[address]).handle(&Request::new("GET", "/")).unwrap_err().status());
+
+        let many = Box::leak(format!("HTTP/1.1 200 OK\r\n{}\r\n", "X: 1\r\n".repeat(1000)).into_boxed_str());
+        let (address, _) = upstream(many, 1);
+        assert_eq!(502, ReverseProxy::new(&[address]).handle(&Request::new("GET", "/")).unwrap_err().status());
+    }
+
+    #[test]
+    fn broken_client_chunks_are_400() {
+        //connections queue up on it unanswered, which is all the body needs
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let proxy = ReverseProxy::new(std::slice::from_ref(&address));
+        let client = TestClient::new(Router::new().mount("/", proxy));
+
+        let response = client.raw(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabcXX0\r\n\r\n").unwrap();
+        assert_eq!(400, response.status);
+    }
+
+    #[test]
+    fn unreachable_upstreams_are_502() {
+        let dead = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let proxy = ReverseProxy::new(&[dead]);
+        assert_eq!(502, proxy.handle(&Request::new("GET", "/")).unwrap_err().status());
+    }
+}
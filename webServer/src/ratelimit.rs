@@ -0,0 +1,233 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::request::Request;
+use crate::response::Response;
+
+//every so often buckets that have refilled completely are forgotten; a full bucket behaves
+//exactly like a missing one, so nothing is lost
+const SWEEP_EVERY: Duration = Duration::from_secs(60);
+//clients we keep a bucket each for; past that, the one not seen for longest is forgotten, which
+//at worst hands it a full bucket again
+const MAX_CLIENTS: usize = 100_000;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    last_used: u64,
+}
+
+struct Buckets {
+    clients: HashMap<String, Bucket>,
+    //last use -> client, oldest first, like the file cache's
+    order: BTreeMap<u64, String>,
+    tick: u64,
+    swept: Option<Instant>,
+}
+
+/// A token bucket per client: each request takes a token, tokens come back at `per_second`
+/// and at most `burst` of them are saved up.
+///
+/// Clients are told apart by their IP address, IPv6 ones by their /64 since a single host
+/// usually has a whole one to pick addresses from. With `key_header` set, requests coming from a
+/// `trust`ed proxy are told apart by that header instead, such as an API key the proxy checked
+/// or the client address it saw; anyone else could send a new value with every request.
+/// One limiter is shared by every worker.
+pub struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    key_header: Option<String>,
+    trusted: Vec<IpAddr>,
+    max_clients: usize,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    /// # Panics
+    ///
+    /// Panics if `per_second` is not positive or `burst` is less than 1.
+    pub fn new(per_second: f64, burst: u32) -> RateLimiter {
+        assert!(per_second > 0.0, "the rate must be positive");
+        assert!(burst >= 1, "the burst must allow at least one request");
+        RateLimiter {
+            per_second,
+            burst: f64::from(burst),
+            key_header: None,
+            trusted: Vec::new(),
+            max_clients: MAX_CLIENTS,
+            buckets: Mutex::new(Buckets {
+                clients: HashMap::new(),
+                order: BTreeMap::new(),
+                tick: 0,
+                swept: None,
+            }),
+        }
+    }
+
+    pub fn key_header(mut self, name: impl Into<String>) -> RateLimiter {
+        self.key_header = Some(name.into());
+        self
+    }
+
+    /// Believes `key_header` in requests from `proxy`.
+    pub fn trust(mut self, proxy: IpAddr) -> RateLimiter {
+        self.trusted.push(proxy);
+        self
+    }
+
+    /// Takes a token for `request`'s client, or says how long until one is available.
+    pub fn check(&self, request: &Request) -> Result<(), Duration> {
+        self.check_at(&self.key(request), Instant::now())
+    }
+
+    /// The `429 Too Many Requests` answer for a client that has to wait `wait`.
+    pub fn reject(wait: Duration) -> Response {
+        //Retry-After only takes whole seconds, rounding down would invite an early retry
+        let seconds = (wait.as_millis() as u64).div_ceil(1000).max(1);
+        Response::text(429, "Too many requests, slow down.\n").with_header("Retry-After", seconds.to_string())
+    }
+
+    fn key(&self, request: &Request) -> String {
+        let peer = match request.peer {
+            Some(peer) => peer.ip(),
+            None => return String::from("unknown"),
+        };
+        let header = match &self.key_header {
+            Some(name) if self.trusted.contains(&peer) => request.header(name),
+            _ => None,
+        };
+        match header {
+            Some(value) => format!("header:{}", value),
+            None => client_network(peer),
+        }
+    }
+
+    fn check_at(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let Buckets { clients, order, tick, swept } = &mut *buckets;
+        //checked on the way through, at most once a minute, rather than by a thread of its own
+        if swept.is_none_or(|swept| now.saturating_duration_since(swept) >= SWEEP_EVERY) {
+            let (per_second, burst) = (self.per_second, self.burst);
+            clients.retain(|_, b| b.tokens + now.saturating_duration_since(b.updated).as_secs_f64() * per_second < burst);
+            order.retain(|_, key| clients.contains_key(key));
+            *swept = Some(now);
+        }
+
+        if clients.len() >= self.max_clients && !clients.contains_key(key) {
+            if let Some((_, oldest)) = order.pop_first() {
+                clients.remove(&oldest);
+            }
+        }
+        *tick += 1;
+        let bucket = clients.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+            last_used: 0,
+        });
+        order.remove(&bucket.last_used);
+        order.insert(*tick, key.to_string());
+        bucket.last_used = *tick;
+
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.per_second))
+        }
+    }
+}
+
+//the address a client is counted by: IPv4 as it is, IPv6 down to its /64
+fn client_network(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => ip.to_string(),
+            None => {
+                let s = ip.segments();
+                format!("{}/64", Ipv6Addr::new(s[0], s[1], s[2], s[3], 0, 0, 0, 0))
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_a_burst_then_refills() {
+        let limiter = RateLimiter::new(2.0, 3);
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.check_at("10.0.0.1", start).is_ok());
+        }
+        let wait = limiter.check_at("10.0.0.1", start).unwrap_err();
+        assert_eq!(500, wait.as_millis());
+        //other clients have buckets of their own
+        assert!(limiter.check_at("10.0.0.2", start).is_ok());
+
+        assert!(limiter.check_at("10.0.0.1", start + Duration::from_millis(500)).is_ok());
+        assert!(limiter.check_at("10.0.0.1", start + Duration::from_millis(600)).is_err());
+    }
+
+    #[test]
+    fn keys_by_header_only_from_trusted_proxies() {
+        let limiter = RateLimiter::new(1.0, 1).key_header("X-Api-Key").trust("10.0.0.1".parse().unwrap());
+        let mut request = Request::new("GET", "/");
+        request.peer = Some("192.0.2.7:5000".parse().unwrap());
+        assert_eq!("192.0.2.7", limiter.key(&request));
+
+        //a client sending the header itself could pick a fresh bucket every time
+        request.headers.append("X-Api-Key", "abc");
+        assert_eq!("192.0.2.7", limiter.key(&request));
+
+        request.peer = Some("10.0.0.1:5000".parse().unwrap());
+        assert_eq!("header:abc", limiter.key(&request));
+    }
+
+    #[test]
+    fn forgets_full_buckets_and_caps_clients() {
+        let mut limiter = RateLimiter::new(1.0, 1);
+        limiter.max_clients = 2;
+        let start = Instant::now();
+        assert!(limiter.check_at("a", start).is_ok());
+        assert!(limiter.check_at("b", start).is_ok());
+        assert!(limiter.check_at("a", start).is_err());
+        //past the cap the client not seen for longest, b, makes room; new clients get a bucket
+        //of their own and a returning one is still held to its limit
+        assert!(limiter.check_at("c", start).is_ok());
+        assert!(limiter.check_at("d", start).is_ok());
+        assert!(limiter.check_at("d", start).is_err());
+        assert_eq!(2, limiter.buckets.lock().unwrap().clients.len());
+
+        //a sweep drops the buckets that have refilled
+        assert!(limiter.check_at("d", start + SWEEP_EVERY).is_ok());
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(1, buckets.clients.len());
+        assert_eq!(1, buckets.order.len());
+    }
+
+    #[test]
+    fn counts_ipv6_clients_by_their_64() {
+        let limiter = RateLimiter::new(1.0, 1);
+        let mut request = Request::new("GET", "/");
+        request.peer = Some("[2001:db8:1:2:aaaa::1]:5000".parse().unwrap());
+        assert_eq!("2001:db8:1:2::/64", limiter.key(&request));
+        request.peer = Some("[::ffff:192.0.2.7]:5000".parse().unwrap());
+        assert_eq!("192.0.2.7", limiter.key(&request));
+    }
+
+    #[test]
+    fn rejects_with_retry_after() {
+        let response = RateLimiter::reject(Duration::from_millis(1200));
+        assert_eq!(429, response.status);
+        assert_eq!(Some("2"), response.headers.get("Retry-After"));
+    }
+}
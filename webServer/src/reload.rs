@@ -0,0 +1,190 @@
+//! Picking up changes without a restart: `Watcher` notices when files change on disk and
+//! `Swap` replaces a value that other threads are using, without stopping them.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// A value that can be replaced while other threads use it.
+///
+/// `load` hands out the current value; a request that loaded it keeps using that one until it
+/// is done, even if a new one is stored in the meantime, so nothing sees half of a reload.
+pub struct Swap<T> {
+    current: RwLock<Arc<T>>,
+}
+
+impl<T> Swap<T> {
+    pub fn new(value: T) -> Swap<T> {
+        Swap {
+            current: RwLock::new(Arc::new(value)),
+        }
+    }
+
+    pub fn load(&self) -> Arc<T> {
+        Arc::clone(&self.current.read().unwrap())
+    }
+
+    pub fn store(&self, value: T) {
+        *self.current.write().unwrap() = Arc::new(value);
+    }
+}
+
+//modification time and size; a quick edit can keep the mtime (coarse clocks) but rarely both
+type Stamp = (Option<SystemTime>, u64);
+
+/// Polls files and directories for changes. There's no OS notification in std, so `changed`
+/// compares modification times and sizes with the last look, walking directories recursively.
+/// Names starting with a dot (editor swap files, `.git`) are ignored.
+pub struct Watcher {
+    paths: Vec<PathBuf>,
+    snapshot: BTreeMap<PathBuf, Stamp>,
+}
+
+impl Watcher {
+    pub fn new<P: AsRef<Path>>(paths: &[P]) -> Watcher {
+        let paths: Vec<PathBuf> = paths.iter().map(|p| p.as_ref().to_path_buf()).collect();
+        let snapshot = scan(&paths);
+        Watcher { paths, snapshot }
+    }
+
+    /// Changes what is watched, keeping what was seen of the paths watched before, so an edit
+    /// made since the last look is still noticed by the next one.
+    pub fn watch<P: AsRef<Path>>(&mut self, paths: &[P]) {
+        let paths: Vec<PathBuf> = paths.iter().map(|p| p.as_ref().to_path_buf()).collect();
+        self.snapshot.retain(|seen, _| paths.iter().any(|path| seen.starts_with(path)));
+        let added: Vec<PathBuf> = paths.iter().filter(|path| !self.paths.contains(path)).cloned().collect();
+        self.snapshot.extend(scan(&added));
+        self.paths = paths;
+    }
+
+    /// Whether anything was modified, created or deleted since the last call (or `new`).
+    pub fn changed(&mut self) -> bool {
+        let snapshot = scan(&self.paths);
+        if snapshot == self.snapshot {
+            return false;
+        }
+        self.snapshot = snapshot;
+        true
+    }
+
+    /// Blocks, looking every `interval`, until something changes.
+    pub fn wait(&mut self, interval: Duration) {
+        while !self.changed() {
+            thread::sleep(interval);
+        }
+    }
+}
+
+fn scan(paths: &[PathBuf]) -> BTreeMap<PathBuf, Stamp> {
+    let mut snapshot = BTreeMap::new();
+    let mut walked = HashSet::new();
+    for path in paths {
+        //a missing path is just absent from the snapshot, so its appearance counts as a change
+        let _ = visit(path, &mut walked, &mut snapshot);
+    }
+    snapshot
+}
+
+//symlinks are followed, `walked` holds the real paths of the directories already walked so a
+//link back up the tree isn't followed round and round
+fn visit(path: &Path, walked: &mut HashSet<PathBuf>, snapshot: &mut BTreeMap<PathBuf, Stamp>) -> io::Result<()> {
+    let metadata = fs::metadata(path)?;
+    if metadata.is_file() {
+        snapshot.insert(path.to_path_buf(), (metadata.modified().ok(), metadata.len()));
+    } else {
+        //a directory's own mtime moves with every file created in it, hidden ones included;
+        //its entries are compared one by one instead
+        snapshot.insert(path.to_path_buf(), (None, 0));
+    }
+    if metadata.is_dir() && walked.insert(fs::canonicalize(path)?) {
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            if !entry.file_name().to_string_lossy().starts_with('.') {
+                let _ = visit(&entry.path(), walked, snapshot);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swap_keeps_loaded_values_alive() {
+        let swap = Swap::new(String::from("old"));
+        let before = swap.load();
+        swap.store(String::from("new"));
+        assert_eq!("old", *before);
+        assert_eq!("new", *swap.load());
+    }
+
+    #[test]
+    fn notices_edits_additions_and_removals() {
+        let dir = std::env::temp_dir().join(format!("webserver-watch-{}", std::process::id()));
+        fs::create_dir_all(dir.join("css")).unwrap();
+        fs::write(dir.join("index.html"), "<p>hi</p>").unwrap();
+
+        let mut watcher = Watcher::new(&[&dir]);
+        assert!(!watcher.changed());
+
+        fs::write(dir.join("index.html"), "<p>hello</p>").unwrap();
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+
+        fs::write(dir.join(".index.html.swp"), "editor noise").unwrap();
+        assert!(!watcher.changed());
+
+        fs::write(dir.join("css").join("site.css"), "p {}").unwrap();
+        assert!(watcher.changed());
+        fs::remove_file(dir.join("css").join("site.css")).unwrap();
+        assert!(watcher.changed());
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(watcher.changed());
+    }
+
+    #[test]
+    fn symlink_loops_are_walked_once() {
+        let dir = std::env::temp_dir().join(format!("webserver-watch-loop-{}", std::process::id()));
+        fs::create_dir_all(dir.join("a")).unwrap();
+        fs::write(dir.join("a").join("page.html"), "<p>hi</p>").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&dir, dir.join("a").join("up")).unwrap();
+
+        let mut watcher = Watcher::new(&[&dir]);
+        assert!(watcher.snapshot.len() < 10);
+        fs::write(dir.join("a").join("page.html"), "<p>hello</p>").unwrap();
+        assert!(watcher.changed());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_snapshot_when_the_paths_change() {
+        let dir = std::env::temp_dir().join(format!("webserver-watch-paths-{}", std::process::id()));
+        fs::create_dir_all(dir.join("one")).unwrap();
+        fs::create_dir_all(dir.join("two")).unwrap();
+        fs::write(dir.join("one").join("index.html"), "<p>1</p>").unwrap();
+        fs::write(dir.join("two").join("index.html"), "<p>2</p>").unwrap();
+
+        let mut watcher = Watcher::new(&[dir.join("one")]);
+        //an edit made while the watched paths are being swapped is still seen afterwards
+        fs::write(dir.join("one").join("index.html"), "<p>one</p>").unwrap();
+        watcher.watch(&[dir.join("one"), dir.join("two")]);
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+
+        watcher.watch(&[dir.join("two")]);
+        assert!(!watcher.changed());
+        fs::write(dir.join("two").join("index.html"), "<p>two</p>").unwrap();
+        assert!(watcher.changed());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,398 @@
+use std::io::prelude::*;
+use std::io;
+use std::net::SocketAddr;
+
+use crate::error::HttpError;
+use crate::headers::Headers;
+use crate::url::{self, Params};
+
+//bytes of trailer fields we skip after the last chunk before giving up
+const MAX_TRAILERS: usize = 8 * 1024;
+
+/// Upper bounds on what we are willing to read from a client for a single request.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Longest request line or header line we accept.
+    pub max_line: usize,
+    /// Most header lines we accept.
+    pub max_headers: usize,
+    /// Largest body we buffer; anything bigger is answered with 413.
+    pub max_body: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_line: 8 * 1024,
+            max_headers: 100,
+            max_body: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    /// The target exactly as the client sent it.
+    pub target: String,
+    /// Decoded path with dot segments removed, what routes match against.
+    pub path: String,
+    pub query: Params,
+    pub version: String,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    /// The client's address, when the request came over a socket.
+    pub peer: Option<SocketAddr>,
+}
+
+impl Request {
+    /// Builds a request by hand, e.g. for tests. A target that doesn't decode is kept as the path as-is.
+    pub fn new(method: &str, target: &str) -> Request {
+        let (path, query) = url::split_target(target).unwrap_or_else(|_| (target.to_string(), Params::new()));
+        Request {
+            method: method.to_string(),
+            target: target.to_string(),
+            path,
+            query,
+            version: String::from("HTTP/1.1"),
+            headers: Headers::new(),
+            body: Vec::new(),
+            peer: None,
+        }
+    }
+
+    /// Reads one request (request line, headers and body) from `reader`.
+    ///
+    /// The body is read in full, either by `Content-Length` or by chunked transfer coding,
+    /// and refused with a 413 error when it would exceed `limits.max_body`.
+    pub fn read_from<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Request, HttpError> {
+        let mut request = Request::read_head(reader, limits)?;
+        request.read_body(reader, limits)?;
+        Ok(request)
+    }
+
+    /// Reads the request line and headers, leaving the body on `reader` for `read_body` or
+    /// `body_reader`.
+    pub fn read_head<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Request, HttpError> {
+        //a client may send empty lines before the request line, RFC 7230 says to skip them
+        let mut request_line = read_line(reader, limits.max_line)?;
+        while request_line.is_empty() {
+            request_line = read_line(reader, limits.max_line)?;
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(m), Some(t), Some(v), None) => (m, t, v),
+            _ => return Err(HttpError::bad_request("malformed request line")),
+        };
+        if !version.starts_with("HTTP/1.") {
+            return Err(HttpError::new(505, "only HTTP/1.x is supported"));
+        }
+
+        let (path, query) = url::split_target(target)?;
+
+        let mut headers = Headers::new();
+        loop {
+            let line = read_line(reader, limits.max_line)?;
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() == limits.max_headers {
+                return Err(HttpError::new(431, "too many headers"));
+            }
+            let (name, value) = match line.find(':') {
+                Some(i) => (&line[..i], line[i + 1..].trim()),
+                None => return Err(HttpError::bad_request("malformed header line")),
+            };
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return Err(HttpError::bad_request("malformed header name"));
+            }
+            headers.append(name, value);
+        }
+
+        Ok(Request {
+            method: method.to_string(),
+            target: target.to_string(),
+            path,
+            query,
+            version: version.to_string(),
+            headers,
+            body: Vec::new(),
+            peer: None,
+        })
+    }
+
+    /// Reads the whole body into `self.body`, see `read_from`.
+    pub fn read_body<R: BufRead>(&mut self, reader: &mut R, limits: &Limits) -> Result<(), HttpError> {
+        self.body = match self.framing()? {
+            Framing::Chunked => read_chunked(reader, limits.max_body)?,
+            Framing::Length(length) => read_exact(reader, length, limits.max_body)?,
+        };
+        Ok(())
+    }
+
+    /// The body as it arrives on `reader`, for handlers that stream it instead of having it
+    /// read in full. It ends where the request says it does, so nothing past it is read.
+    pub fn body_reader<'r, R: BufRead + 'r>(&self, reader: &'r mut R) -> Result<Box<dyn Read + 'r>, HttpError> {
+        Ok(match self.framing()? {
+            Framing::Chunked => Box::new(ChunkedReader::new(reader)),
+            Framing::Length(length) => Box::new(reader.take(length as u64)),
+        })
+    }
+
+    pub(crate) fn framing(&self) -> Result<Framing, HttpError> {
+        let chunked = self
+            .header("Transfer-Encoding")
+            .map(|v| v.to_ascii_lowercase().contains("chunked"))
+            .unwrap_or(false);
+        if chunked {
+            return Ok(Framing::Chunked);
+        }
+        match self.header("Content-Length") {
+            Some(value) => value
+                .parse::<usize>()
+                .map(Framing::Length)
+                .map_err(|_| HttpError::bad_request("invalid Content-Length")),
+            None => Ok(Framing::Length(0)),
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// The media type of the body without its parameters, lowercased.
+    pub fn content_type(&self) -> Option<String> {
+        self.header("Content-Type")
+            .map(|v| v.split(';').next().unwrap_or("").trim().to_ascii_lowercase())
+    }
+}
+
+/// How the end of a request body is found; a request without either header has no body.
+pub(crate) enum Framing {
+    Chunked,
+    Length(usize),
+}
+
+fn read_exact<R: BufRead>(reader: &mut R, length: usize, limit: usize) -> Result<Vec<u8>, HttpError> {
+    //refuse before reading anything, the client told us how big it is going to be
+    if length > limit {
+        return Err(HttpError::payload_too_large(limit));
+    }
+
+    let mut body = vec![0; length];
+    reader
+        .read_exact(&mut body)
+        .map_err(|_| HttpError::bad_request("request body shorter than Content-Length"))?;
+    Ok(body)
+}
+
+fn read_chunked<R: BufRead>(reader: &mut R, limit: usize) -> Result<Vec<u8>, HttpError> {
+    let mut body = Vec::new();
+    loop {
+        let line = read_line(reader, 1024)?;
+        //chunk extensions come after a ';' and we have no use for them
+        let size = line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| HttpError::bad_request("invalid chunk size"))?;
+        if size == 0 {
+            break;
+        }
+        //the size comes from the client, body.len() + size could overflow
+        if size > limit - body.len() {
+            return Err(HttpError::payload_too_large(limit));
+        }
+
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader
+            .read_exact(&mut body[start..])
+            .map_err(|_| HttpError::bad_request("truncated chunk"))?;
+        read_chunk_end(reader)?;
+    }
+
+    skip_trailers(reader)?;
+    Ok(body)
+}
+
+//the CRLF after a chunk's data; anything else there is a framing error, not a long line
+fn read_chunk_end<R: BufRead>(reader: &mut R) -> Result<(), HttpError> {
+    match read_line(reader, 2) {
+        Ok(line) if line.is_empty() => Ok(()),
+        _ => Err(HttpError::bad_request("chunk not followed by CRLF")),
+    }
+}
+
+//trailer fields are allowed after the last chunk, skip them up to the empty line
+fn skip_trailers<R: BufRead>(reader: &mut R) -> Result<(), HttpError> {
+    let mut total = 0;
+    loop {
+        let line = read_line(reader, MAX_TRAILERS)?;
+        if line.is_empty() {
+            return Ok(());
+        }
+        total += line.len();
+        if total > MAX_TRAILERS {
+            return Err(HttpError::new(431, "trailer section too long"));
+        }
+    }
+}
+
+/// Decodes a chunked body as it is read, without buffering more than one chunk header.
+///
+/// The chunk framing is held to the same limits as `read_chunked`'s. When it is broken the
+/// `io::Error` returned wraps the `HttpError` to answer with, see `HttpError::from_body`.
+pub(crate) struct ChunkedReader<R> {
+    inner: R,
+    remaining: u64,
+    done: bool,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    pub(crate) fn new(inner: R) -> ChunkedReader<R> {
+        ChunkedReader {
+            inner,
+            remaining: 0,
+            done: false,
+        }
+    }
+
+    fn next_chunk(&mut self) -> Result<(), HttpError> {
+        let line = read_line(&mut self.inner, 1024)?;
+        let size = line.split(';').next().unwrap_or("").trim();
+        self.remaining = u64::from_str_radix(size, 16).map_err(|_| HttpError::bad_request("invalid chunk size"))?;
+        if self.remaining == 0 {
+            skip_trailers(&mut self.inner)?;
+            self.done = true;
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            self.next_chunk().map_err(body_error)?;
+            if self.done {
+                return Ok(0);
+            }
+        }
+
+        let max = buf.len().min(self.remaining as usize);
+        let read = self.inner.read(&mut buf[..max])?;
+        if read == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed inside a chunk"));
+        }
+        self.remaining -= read as u64;
+        if self.remaining == 0 {
+            read_chunk_end(&mut self.inner).map_err(body_error)?;
+        }
+        Ok(read)
+    }
+}
+
+fn body_error(err: HttpError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+//reads a line terminated by LF (with or without CR) and returns it without the terminator
+pub(crate) fn read_line<R: BufRead>(reader: &mut R, max: usize) -> Result<String, HttpError> {
+    let mut line = Vec::new();
+    let read = reader
+        .by_ref()
+        .take(max as u64 + 2)
+        .read_until(b'\n', &mut line)?;
+    if read == 0 {
+        return Err(HttpError::bad_request("connection closed before request was complete"));
+    }
+    if line.last() != Some(&b'\n') {
+        return Err(HttpError::new(431, "request line or header too long"));
+    }
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line).map_err(|_| HttpError::bad_request("request head is not valid UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str, limits: &Limits) -> Result<Request, HttpError> {
+        Request::read_from(&mut raw.as_bytes(), limits)
+    }
+
+    #[test]
+    fn reads_body_by_content_length() {
+        let raw = "POST /form?x=%2F HTTP/1.1\r\nHost: localhost\r\nContent-Length: 11\r\n\r\nhello=world";
+        let request = parse(raw, &Limits::default()).unwrap();
+
+        assert_eq!("POST", request.method);
+        assert_eq!("/form?x=%2F", request.target);
+        assert_eq!("/form", request.path);
+        assert_eq!(Some("/"), request.query.get("x"));
+        assert_eq!(Some("localhost"), request.header("host"));
+        assert_eq!(b"hello=world".to_vec(), request.body);
+    }
+
+    #[test]
+    fn reads_chunked_body() {
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\n\r\n";
+        let request = parse(raw, &Limits::default()).unwrap();
+
+        assert_eq!(b"hello world".to_vec(), request.body);
+    }
+
+    #[test]
+    fn streams_the_body_without_reading_past_it() {
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\nNEXT";
+        let mut reader = raw.as_bytes();
+        let request = Request::read_head(&mut reader, &Limits::default()).unwrap();
+        let mut body = String::new();
+        request.body_reader(&mut reader).unwrap().read_to_string(&mut body).unwrap();
+        assert_eq!("hello world", body);
+        assert_eq!(b"NEXT", reader);
+    }
+
+    #[test]
+    fn oversized_body_is_413() {
+        let limits = Limits { max_body: 4, ..Limits::default() };
+        let raw = "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello";
+        assert_eq!(413, parse(raw, &limits).unwrap_err().status());
+
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n";
+        assert_eq!(413, parse(raw, &limits).unwrap_err().status());
+    }
+
+    #[test]
+    fn huge_chunk_size_is_413() {
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\na\r\nffffffffffffffff\r\nb\r\n0\r\n\r\n";
+        assert_eq!(413, parse(raw, &Limits::default()).unwrap_err().status());
+    }
+
+    #[test]
+    fn streamed_chunks_are_held_to_the_same_limits() {
+        let status = |raw: &[u8]| {
+            let mut body = Vec::new();
+            let err = ChunkedReader::new(raw).read_to_end(&mut body).unwrap_err();
+            HttpError::from_body(&err).unwrap().status()
+        };
+        assert_eq!(400, status(b"3\r\nabcXX0\r\n\r\n"));
+        assert_eq!(431, status(&[b'1'; 100_000]));
+        let trailers = format!("0\r\n{}\r\n", "X-Trailer: 1\r\n".repeat(1000));
+        assert_eq!(431, status(trailers.as_bytes()));
+
+        let mut body = Vec::new();
+        ChunkedReader::new(&b"3\r\nabc\r\n0\r\nX-Trailer: 1\r\n\r\n"[..]).read_to_end(&mut body).unwrap();
+        assert_eq!(b"abc", &body[..]);
+    }
+
+    #[test]
+    fn malformed_request_line_is_400() {
+        assert_eq!(400, parse("GET /\r\n\r\n", &Limits::default()).unwrap_err().status());
+    }
+}
@@ -0,0 +1,261 @@
+use std::fmt;
+use std::io::prelude::*;
+use std::io;
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::error::HttpError;
+use crate::headers::{is_token, Headers};
+use crate::server::Connection;
+use crate::sse::EventStream;
+
+/// What takes over the connection after a `101 Switching Protocols` response has been written.
+pub type Upgrade = Box<dyn FnOnce(&mut dyn Connection) + Send>;
+
+pub enum Body {
+    Bytes(Vec<u8>),
+    /// Bytes kept elsewhere too, like a file in the cache, sent without copying them first.
+    Shared(Arc<[u8]>),
+    /// Copied from the reader as it produces data, e.g. from an upstream server or a child process.
+    /// Without a known length the end of the body is signalled by closing the connection.
+    Stream {
+        reader: Box<dyn Read + Send>,
+        length: Option<u64>,
+    },
+    /// Server-sent events, written as they come until the feed ends. See `Response::events`.
+    Events(EventStream),
+}
+
+impl Body {
+    /// The bytes of a fixed body; streaming bodies have none up front.
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Body::Bytes(bytes) => bytes,
+            Body::Shared(bytes) => bytes,
+            Body::Stream { .. } | Body::Events(_) => &[],
+        }
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Headers,
+    pub body: Body,
+    pub upgrade: Option<Upgrade>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: Body::Bytes(Vec::new()),
+            upgrade: None,
+        }
+    }
+
+    pub fn html(status: u16, contents: impl Into<String>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(contents.into().into_bytes())
+    }
+
+    pub fn text(status: u16, contents: impl Into<String>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(contents.into().into_bytes())
+    }
+
+    pub fn json<T: Serialize>(status: u16, value: &T) -> Result<Response, HttpError> {
+        let body = serde_json::to_vec(value).map_err(|e| HttpError::new(500, e.to_string()))?;
+        Ok(Response::new(status)
+            .with_header("Content-Type", "application/json")
+            .with_body(body))
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Response {
+        self.headers.set(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, body: Vec<u8>) -> Response {
+        self.body = Body::Bytes(body);
+        self
+    }
+
+    pub fn with_shared(mut self, body: Arc<[u8]>) -> Response {
+        self.body = Body::Shared(body);
+        self
+    }
+
+    pub fn with_stream<R: Read + Send + 'static>(mut self, reader: R, length: Option<u64>) -> Response {
+        self.body = Body::Stream {
+            reader: Box::new(reader),
+            length,
+        };
+        self
+    }
+
+    /// Hands the connection to `upgrade` once this (101) response has been sent.
+    pub fn with_upgrade<F>(mut self, upgrade: F) -> Response
+    where
+        F: FnOnce(&mut dyn Connection) + Send + 'static,
+    {
+        self.upgrade = Some(Box::new(upgrade));
+        self
+    }
+
+    /// Writes the status line, headers and body. `Content-Length` is filled in for fixed
+    /// bodies, streamed ones simply end when we close the connection; we ask the client to close
+    /// either way since we serve one request per connection.
+    /// A 101 response is sent as is: the connection stays open for the new protocol.
+    pub fn write_to<W: Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(self.head().as_bytes())?;
+        match &mut self.body {
+            Body::Bytes(bytes) => writer.write_all(bytes)?,
+            Body::Shared(bytes) => writer.write_all(bytes)?,
+            Body::Stream { reader, .. } => {
+                io::copy(reader, writer)?;
+            }
+            Body::Events(events) => {
+                writer.flush()?;
+                return events.write_to(writer);
+            }
+        }
+        //flush will wait and prevent the program from continuing until all the bytes are written to the connection
+        writer.flush()
+    }
+
+    /// Writes the status line and headers only, the answer to a HEAD request. `Content-Length`
+    /// is still the length of the body a GET would have got.
+    pub fn write_head_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(self.head().as_bytes())?;
+        writer.flush()
+    }
+
+    fn head(&self) -> String {
+        let switching = self.status == 101;
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason_phrase(self.status));
+        for (name, value) in self.headers.iter() {
+            if !switching && (name.eq_ignore_ascii_case("Content-Length") || name.eq_ignore_ascii_case("Connection")) {
+                continue;
+            }
+            //a handler may put what a client sent into a header (a redirect's Location); a line
+            //break in it would start headers, or a body, of the client's choosing
+            if !is_token(name) {
+                continue;
+            }
+            let value = value.replace(['\r', '\n', '\0'], " ");
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if !switching {
+            match &self.body {
+                //these never have a body, a length of 0 would claim the file is empty
+                _ if self.status == 204 || self.status == 304 => {}
+                Body::Bytes(_) | Body::Shared(_) => {
+                    head.push_str(&format!("Content-Length: {}\r\n", self.body.as_bytes().len()))
+                }
+                Body::Stream { length: Some(length), .. } => head.push_str(&format!("Content-Length: {}\r\n", length)),
+                _ => {}
+            }
+            head.push_str("Connection: close\r\n");
+        }
+        head.push_str("\r\n");
+        head
+    }
+}
+
+impl fmt::Debug for Response {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Response")
+            .field("status", &self.status)
+            .field("headers", &self.headers)
+            .field("body", &self.body.as_bytes().len())
+            .field("upgrade", &self.upgrade.is_some())
+            .finish()
+    }
+}
+
+impl From<HttpError> for Response {
+    fn from(err: HttpError) -> Response {
+        Response::text(err.status(), format!("{}\n", err))
+    }
+}
+
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_status_headers_and_body() {
+        let mut response = Response::html(404, "<p>gone</p>");
+        let mut out = Vec::new();
+        response.write_to(&mut out).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(out.contains("Content-Type: text/html; charset=utf-8\r\n"));
+        assert!(out.contains("Content-Length: 11\r\n"));
+        assert!(out.ends_with("\r\n\r\n<p>gone</p>"));
+    }
+
+    #[test]
+    fn head_leaves_the_body_out() {
+        let mut out = Vec::new();
+        Response::text(200, "hello").write_head_to(&mut out).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Content-Length: 5\r\n"));
+        assert!(out.ends_with("Connection: close\r\n\r\n"));
+    }
+
+    #[test]
+    fn line_breaks_cannot_split_the_head() {
+        let mut out = Vec::new();
+        Response::new(302)
+            .with_header("Location", "/next\r\nSet-Cookie: admin=1\r\n\r\n<script>")
+            .with_header("X-Bad\r\nName", "1")
+            .with_header("Bad Name", "1")
+            .write_head_to(&mut out)
+            .unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Location: /next  Set-Cookie: admin=1    <script>\r\n"));
+        assert!(!out.contains("Name"));
+        assert_eq!(1, out.matches("\r\n\r\n").count());
+    }
+}
@@ -0,0 +1,346 @@
+use std::io::Read;
+use std::sync::Arc;
+
+use crate::error::HttpError;
+use crate::errorpages::ErrorPages;
+use crate::ratelimit::RateLimiter;
+use crate::request::Request;
+use crate::response::Response;
+
+/// Anything that can answer a request. Closures taking `&Request` get this for free.
+///
+/// Handlers are shared by every worker in the pool, hence `Send + Sync`.
+pub trait Handler: Send + Sync + 'static {
+    fn handle(&self, request: &Request) -> Result<Response, HttpError>;
+
+    /// Handlers that would rather read the body as it arrives, like the reverse proxy, say so
+    /// here. The server then leaves it unread and calls `handle_stream` instead of `handle`.
+    fn streams_body(&self) -> bool {
+        false
+    }
+
+    /// Answers a request whose body is read from `body`; `request.body` is empty.
+    fn handle_stream(&self, request: &Request, _body: &mut dyn Read) -> Result<Response, HttpError> {
+        self.handle(request)
+    }
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&Request) -> Result<Response, HttpError> + Send + Sync + 'static,
+{
+    fn handle(&self, request: &Request) -> Result<Response, HttpError> {
+        self(request)
+    }
+}
+
+//so a handler can outlive the router it was mounted on, like the proxies kept across reloads
+impl<H: Handler + ?Sized> Handler for Arc<H> {
+    fn handle(&self, request: &Request) -> Result<Response, HttpError> {
+        (**self).handle(request)
+    }
+
+    fn streams_body(&self) -> bool {
+        (**self).streams_body()
+    }
+
+    fn handle_stream(&self, request: &Request, body: &mut dyn Read) -> Result<Response, HttpError> {
+        (**self).handle_stream(request, body)
+    }
+}
+
+struct Route {
+    method: String,
+    path: String,
+    handler: Box<dyn Handler>,
+}
+
+/// Picks a handler by method and exact (normalized) path, then by path prefix.
+///
+/// With virtual hosts added, a request whose `Host` names one of them is passed whole to that
+/// host's router; the routes here serve every other host.
+pub struct Router {
+    hosts: Vec<(Vec<String>, Router)>,
+    routes: Vec<Route>,
+    mounts: Vec<(String, Box<dyn Handler>)>,
+    limiters: Vec<(String, Arc<RateLimiter>)>,
+    fallback: Option<Box<dyn Handler>>,
+    errors: Option<ErrorPages>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router {
+            hosts: Vec::new(),
+            routes: Vec::new(),
+            mounts: Vec::new(),
+            limiters: Vec::new(),
+            fallback: None,
+            errors: None,
+        }
+    }
+
+    pub fn route<H: Handler>(mut self, method: &str, path: &str, handler: H) -> Router {
+        self.routes.push(Route {
+            method: method.to_ascii_uppercase(),
+            path: path.to_string(),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get<H: Handler>(self, path: &str, handler: H) -> Router {
+        self.route("GET", path, handler)
+    }
+
+    pub fn post<H: Handler>(self, path: &str, handler: H) -> Router {
+        self.route("POST", path, handler)
+    }
+
+    /// Sends every request under `prefix` to `handler`, whatever the method. `/api` matches
+    /// `/api` and `/api/users` but not `/apiary`. Exact routes win, then the longest prefix.
+    pub fn mount<H: Handler>(mut self, prefix: &str, handler: H) -> Router {
+        self.mounts.push((prefix.trim_end_matches('/').to_string(), Box::new(handler)));
+        self
+    }
+
+    /// Rate limits requests under `prefix`, before any handler runs. Like mounts, the longest
+    /// matching prefix applies, so `/api/login` can be stricter than the rest of `/api`.
+    /// An `Arc<RateLimiter>` shares its counts with every router it is passed to.
+    pub fn limit(mut self, prefix: &str, limiter: impl Into<Arc<RateLimiter>>) -> Router {
+        self.limiters.push((prefix.trim_end_matches('/').to_string(), limiter.into()));
+        self
+    }
+
+    /// Sends requests for any of `names` to `router`. Names are matched case-insensitively and
+    /// without the port; `*.example.com` matches any subdomain, but not `example.com` itself.
+    pub fn host<S: AsRef<str>>(mut self, names: &[S], router: Router) -> Router {
+        let names = names.iter().map(|n| n.as_ref().to_ascii_lowercase()).collect();
+        self.hosts.push((names, router));
+        self
+    }
+
+    /// Handler used when no route matches, instead of a bare 404.
+    pub fn fallback<H: Handler>(mut self, handler: H) -> Router {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    /// Renders the errors handlers return as pages, instead of a line of plain text. Under
+    /// `Server`, so are requests it could not read: malformed, too long, a body too big.
+    pub fn errors(mut self, pages: ErrorPages) -> Router {
+        self.errors = Some(pages);
+        self
+    }
+
+    /// Runs the matching handler and turns any error it returns into a response.
+    pub fn dispatch(&self, request: &Request) -> Response {
+        let site = self.site(request);
+        match site.admit(request) {
+            Ok(()) => site.respond(request, None),
+            Err(rejected) => rejected,
+        }
+    }
+
+    /// Whether the handler for `request` reads the body itself, see `Handler::streams_body`.
+    pub fn streams_body(&self, request: &Request) -> bool {
+        self.site(request).find(request).is_ok_and(|handler| handler.streams_body())
+    }
+
+    /// Like `dispatch`, for a request whose body is still to be read from `body`. Handlers that
+    /// don't stream it see an empty body, so check `streams_body` first.
+    pub fn dispatch_stream(&self, request: &Request, body: &mut dyn Read) -> Response {
+        let site = self.site(request);
+        match site.admit(request) {
+            Ok(()) => site.respond(request, Some(body)),
+            Err(rejected) => rejected,
+        }
+    }
+
+    //the router for the virtual host the request is for, or this one
+    pub(crate) fn site(&self, request: &Request) -> &Router {
+        if let Some(host) = request.header("Host").map(host_name) {
+            let site = self.hosts.iter().find(|(names, _)| names.iter().any(|n| host_matches(n, &host)));
+            if let Some((_, router)) = site {
+                return router.site(request);
+            }
+        }
+        self
+    }
+
+    //the rate limit on the request's path, if any, takes a token here; the server asks before it
+    //reads the body, so a client over its limit can't make us take an upload first
+    pub(crate) fn admit(&self, request: &Request) -> Result<(), Response> {
+        let limiter = self
+            .limiters
+            .iter()
+            .filter(|(prefix, _)| under_prefix(&request.path, prefix))
+            .max_by_key(|(prefix, _)| prefix.len());
+        match limiter {
+            Some((_, limiter)) => limiter.check(request).map_err(RateLimiter::reject),
+            None => Ok(()),
+        }
+    }
+
+    //runs the handler for a request `admit` let through
+    pub(crate) fn respond(&self, request: &Request, body: Option<&mut dyn Read>) -> Response {
+        let result = self.find(request).and_then(|handler| match body {
+            Some(body) if handler.streams_body() => handler.handle_stream(request, body),
+            _ => handler.handle(request),
+        });
+        match result {
+            Ok(response) => response,
+            Err(err) => self.error(err),
+        }
+    }
+
+    //the error as this site shows them, also for requests the server failed to read
+    pub(crate) fn error(&self, err: HttpError) -> Response {
+        match &self.errors {
+            Some(pages) => pages.render(&err),
+            None => err.into(),
+        }
+    }
+
+    fn find(&self, request: &Request) -> Result<&dyn Handler, HttpError> {
+        let mut path_matched = false;
+        for route in self.routes.iter().filter(|r| r.path == request.path) {
+            //HEAD is answered like GET, handle_connection then writes the head only
+            if route.method == request.method || (route.method == "GET" && request.method == "HEAD") {
+                return Ok(&*route.handler);
+            }
+            path_matched = true;
+        }
+
+        if path_matched {
+            return Err(HttpError::new(405, format!("{} is not allowed here", request.method)));
+        }
+        let mount = self
+            .mounts
+            .iter()
+            .filter(|(prefix, _)| under_prefix(&request.path, prefix))
+            .max_by_key(|(prefix, _)| prefix.len());
+        if let Some((_, handler)) = mount {
+            return Ok(&**handler);
+        }
+        match &self.fallback {
+            Some(handler) => Ok(&**handler),
+            None => Err(HttpError::not_found()),
+        }
+    }
+}
+
+fn under_prefix(path: &str, prefix: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+//`Host: Example.com:8080` -> `example.com`, `[::1]:80` -> `[::1]`
+fn host_name(header: &str) -> String {
+    let header = header.trim();
+    let end = match header.rfind(':') {
+        Some(i) if !header[i..].contains(']') => i,
+        _ => header.len(),
+    };
+    header[..end].trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host.len() > domain.len() + 1 && host.ends_with(domain) && host[..host.len() - domain.len()].ends_with('.'),
+        None => pattern == host,
+    }
+}
+
+impl Default for Router {
+    fn default() -> Router {
+        Router::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router() -> Router {
+        Router::new()
+            .get("/", |_: &Request| Ok(Response::text(200, "home")))
+            .post("/echo", |r: &Request| Ok(Response::new(200).with_body(r.body.clone())))
+    }
+
+    #[test]
+    fn dispatches_by_method_and_path() {
+        assert_eq!(b"home", router().dispatch(&Request::new("GET", "/?x=1")).body.as_bytes());
+        assert_eq!(405, router().dispatch(&Request::new("GET", "/echo")).status);
+        assert_eq!(404, router().dispatch(&Request::new("GET", "/nope")).status);
+        assert_eq!(b"home", router().dispatch(&Request::new("GET", "/a/..")).body.as_bytes());
+    }
+
+    #[test]
+    fn mounts_match_whole_segments() {
+        let router = router()
+            .mount("/api", |_: &Request| Ok(Response::text(200, "api")))
+            .mount("/api/v2/", |_: &Request| Ok(Response::text(200, "v2")));
+
+        assert_eq!(b"api", router.dispatch(&Request::new("DELETE", "/api/users/1")).body.as_bytes());
+        assert_eq!(b"api", router.dispatch(&Request::new("GET", "/api")).body.as_bytes());
+        assert_eq!(b"v2", router.dispatch(&Request::new("GET", "/api/v2/x")).body.as_bytes());
+        assert_eq!(404, router.dispatch(&Request::new("GET", "/apiary")).status);
+    }
+
+    #[test]
+    fn limits_by_longest_prefix() {
+        let router = router()
+            .limit("/", RateLimiter::new(1.0, 2))
+            .limit("/echo", RateLimiter::new(1.0, 1));
+
+        assert_eq!(200, router.dispatch(&Request::new("POST", "/echo")).status);
+        let limited = router.dispatch(&Request::new("POST", "/echo"));
+        assert_eq!(429, limited.status);
+        assert_eq!(Some("1"), limited.headers.get("Retry-After"));
+
+        //the rest of the site still has its own, larger allowance
+        assert_eq!(200, router.dispatch(&Request::new("GET", "/")).status);
+        assert_eq!(200, router.dispatch(&Request::new("GET", "/")).status);
+        assert_eq!(429, router.dispatch(&Request::new("GET", "/")).status);
+    }
+
+    #[test]
+    fn shared_limiters_and_handlers_outlive_a_router() {
+        let limiter = Arc::new(RateLimiter::new(1.0, 1));
+        let api = Arc::new(|_: &Request| Ok(Response::text(200, "api")));
+        let first = router().limit("/", Arc::clone(&limiter)).mount("/api", Arc::clone(&api));
+        assert_eq!(b"api", first.dispatch(&Request::new("GET", "/api")).body.as_bytes());
+
+        //a router built in its place, as on a reload, goes on from the same count
+        let second = router().limit("/", limiter).mount("/api", api);
+        assert_eq!(429, second.dispatch(&Request::new("GET", "/api")).status);
+    }
+
+    #[test]
+    fn picks_virtual_hosts_by_host_header() {
+        let docs = Router::new().get("/", |_: &Request| Ok(Response::text(200, "docs")));
+        let wiki = Router::new().get("/", |_: &Request| Ok(Response::text(200, "wiki")));
+        let router = router().host(&["docs.internal", "docs.local"], docs).host(&["*.wiki.internal"], wiki);
+
+        let get = |host: &str| {
+            let mut request = Request::new("GET", "/");
+            request.headers.append("Host", host);
+            router.dispatch(&request).body.as_bytes().to_vec()
+        };
+        assert_eq!(b"docs".to_vec(), get("Docs.Internal:7878"));
+        assert_eq!(b"docs".to_vec(), get("docs.local."));
+        assert_eq!(b"wiki".to_vec(), get("team.wiki.internal"));
+        assert_eq!(b"home".to_vec(), get("wiki.internal"));
+        assert_eq!(b"home".to_vec(), get("[::1]:7878"));
+        assert_eq!(b"home", router.dispatch(&Request::new("GET", "/")).body.as_bytes());
+    }
+
+    #[test]
+    fn handler_errors_become_responses() {
+        let router = Router::new().get("/", |_: &Request| Err(HttpError::payload_too_large(1)));
+        assert_eq!(413, router.dispatch(&Request::new("GET", "/")).status);
+    }
+}
@@ -0,0 +1,340 @@
+use std::io::prelude::*;
+use std::io::{self, BufReader};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use rustls::ServerConfig;
+
+use crate::config::Config;
+use crate::reload::Swap;
+use crate::request::{Limits, Request};
+use crate::error::HttpError;
+use crate::response::{Body, Response};
+use crate::router::Router;
+use crate::tls::{self, TlsStream};
+use crate::ThreadPool;
+
+/// A client connection as handlers that take over the socket (like WebSockets) see it.
+pub trait Connection: Read + Write {}
+
+impl<T: Read + Write + ?Sized> Connection for T {}
+
+//reads go through the BufReader so bytes it already pulled off the socket aren't lost,
+//writes go straight to the stream underneath
+struct Buffered<S: Read + Write>(BufReader<S>);
+
+impl<S: Read + Write> Read for Buffered<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<S: Read + Write> Write for Buffered<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.get_mut().flush()
+    }
+}
+
+/// Reads one request from `stream`, answers it through `router` and writes the response back.
+///
+/// Requests we cannot parse (or that are too big) are answered with the matching error status,
+/// the handler is never called for them. If the response upgrades the connection, the upgrade
+/// runs here, on the calling thread, until it is done with the socket.
+pub fn handle_connection<S: Read + Write>(
+    stream: S,
+    peer: Option<SocketAddr>,
+    router: &Router,
+    limits: &Limits,
+) -> io::Result<()> {
+    //a client may send its first frames right behind the upgrade request, so the reader (and
+    //whatever it has buffered) is kept for the upgraded connection rather than dropped
+    let mut stream = Buffered(BufReader::new(stream));
+    let (response, head) = answer(&mut stream.0, peer, router, limits);
+    send(&mut stream, response, head)
+}
+
+//reads a request and works out the response to it, and whether that is to a HEAD request
+fn answer<R: BufRead>(reader: &mut R, peer: Option<SocketAddr>, router: &Router, limits: &Limits) -> (Response, bool) {
+    let request = Request::read_head(reader, limits);
+    //HEAD is routed like GET, only the body stays behind
+    let head = request.as_ref().is_ok_and(|r| r.method == "HEAD");
+    let response = match request {
+        Ok(mut request) => {
+            request.peer = peer;
+            respond(reader, request, router, limits)
+        }
+        //no request to pick a virtual host by, the default site shows it
+        Err(err) => router.error(err),
+    };
+    (response, head)
+}
+
+fn send<S: Read + Write>(stream: &mut Buffered<S>, mut response: Response, head: bool) -> io::Result<()> {
+    if head {
+        response.write_head_to(stream)?;
+    } else {
+        response.write_to(stream)?;
+    }
+    if let Some(upgrade) = response.upgrade.take() {
+        upgrade(stream);
+    }
+    Ok(())
+}
+
+//the rate limit comes first, then handlers that stream the body read it straight off the
+//connection and the rest get it in full
+fn respond<R: BufRead>(reader: &mut R, mut request: Request, router: &Router, limits: &Limits) -> Response {
+    let site = router.site(&request);
+    if let Err(rejected) = site.admit(&request) {
+        return rejected;
+    }
+    if site.streams_body(&request) {
+        return match request.body_reader(reader) {
+            Ok(mut body) => site.respond(&request, Some(&mut body)),
+            Err(err) => site.error(err),
+        };
+    }
+    match request.read_body(reader, limits) {
+        Ok(()) => site.respond(&request, None),
+        Err(err) => site.error(err),
+    }
+}
+
+/// The listeners from a `Config`, bound and ready to hand connections to the thread pool.
+///
+/// Binding happens in `bind` so errors (port in use, unreadable certificate) show up
+/// before `run` starts blocking, and so tests can bind port 0 and ask which port they got.
+///
+/// The router can be replaced while the server runs, see `routes`. Listener settings
+/// (addresses, certificates, threads, limits) are fixed once bound.
+pub struct Server {
+    routes: Arc<Swap<Router>>,
+    limits: Limits,
+    threads: usize,
+    timeout: Duration,
+    max_streams: usize,
+    http: TcpListener,
+    https: Option<(TcpListener, Arc<ServerConfig>)>,
+    redirect_http: bool,
+}
+
+impl Server {
+    pub fn bind(config: &Config, router: Router) -> io::Result<Server> {
+        let http = TcpListener::bind(&config.address)?;
+        let https = match &config.tls {
+            Some(settings) => {
+                let tls_config = tls::load_tls_config(&settings.cert, &settings.key)?;
+                Some((TcpListener::bind(&settings.address)?, tls_config))
+            }
+            None => None,
+        };
+
+        Ok(Server {
+            routes: Arc::new(Swap::new(router)),
+            limits: config.limits(),
+            threads: config.threads,
+            timeout: config.timeout,
+            max_streams: config.max_streams,
+            http,
+            https,
+            redirect_http: config.redirect_http,
+        })
+    }
+
+    /// The active router. Storing a new one here affects connections accepted from then on;
+    /// requests already being handled finish with the router they started with.
+    pub fn routes(&self) -> Arc<Swap<Router>> {
+        Arc::clone(&self.routes)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.http.local_addr()
+    }
+
+    /// Address of the HTTPS listener, if TLS is configured.
+    pub fn tls_local_addr(&self) -> Option<SocketAddr> {
+        self.https.as_ref().and_then(|(listener, _)| listener.local_addr().ok())
+    }
+
+    /// Accepts connections forever. The HTTPS listener, if any, gets its own accepting
+    /// thread; both hand their connections to the same pool. Upgraded connections and event
+    /// streams move to a thread of their own once their response is ready, up to `max_streams`.
+    pub fn run(self) {
+        let pool = Arc::new(ThreadPool::new(self.threads));
+        let limits = self.limits;
+        let timeout = self.timeout;
+        let streams = Arc::new(Streams {
+            open: AtomicUsize::new(0),
+            max: self.max_streams,
+        });
+
+        //in redirect mode the plaintext port answers everything with the redirect, through a router of its own
+        let http_routes = match (&self.https, self.redirect_http) {
+            (Some((listener, _)), true) => {
+                let port = listener.local_addr().map(|a| a.port()).unwrap_or(443);
+                Arc::new(Swap::new(
+                    Router::new().fallback(move |r: &Request| Ok(tls::https_redirect(r, port))),
+                ))
+            }
+            _ => Arc::clone(&self.routes),
+        };
+
+        if let Some((listener, tls_config)) = self.https {
+            let pool = Arc::clone(&pool);
+            let routes = Arc::clone(&self.routes);
+            let streams = Arc::clone(&streams);
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            eprintln!("Failed to accept TLS connection: {}", e);
+                            continue;
+                        }
+                    };
+                    let tls_config = Arc::clone(&tls_config);
+                    let router = routes.load();
+                    let streams = Arc::clone(&streams);
+                    pool.execute(move || {
+                        if let Err(e) = serve_tls(&tls_config, stream, timeout, &router, &limits, &streams) {
+                            eprintln!("TLS connection error: {}", e);
+                        }
+                    });
+                }
+            });
+        }
+
+        //incoming yields connection attempts rather than connections, so an error only means one
+        //attempt failed: it is logged and we keep accepting
+        for stream in self.http.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("Failed to accept connection: {}", e);
+                    continue;
+                }
+            };
+            let router = http_routes.load();
+            let streams = Arc::clone(&streams);
+            pool.execute(move || {
+                if let Err(e) = serve_http(stream, timeout, &router, &limits, &streams) {
+                    eprintln!("Connection error: {}", e);
+                }
+            });
+        }
+    }
+}
+
+//the connections running on threads of their own, see `Config::max_streams`
+struct Streams {
+    open: AtomicUsize,
+    max: usize,
+}
+
+//one of them; the count goes down when it is dropped
+struct StreamSlot(Arc<Streams>);
+
+impl Streams {
+    fn open(self: &Arc<Self>) -> Option<StreamSlot> {
+        self.open
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| Some(open + 1).filter(|&n| n <= self.max))
+            .ok()
+            .map(|_| StreamSlot(Arc::clone(self)))
+    }
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        self.0.open.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+//what the server reads requests from, plain or TLS, and how to say goodbye on it
+trait Accepted: Read + Write + Send + 'static {
+    fn close(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl Accepted for TcpStream {}
+
+impl Accepted for TlsStream {
+    //tell the client we are done, otherwise it can't tell our close from a truncation attack
+    fn close(&mut self) -> io::Result<()> {
+        self.conn.send_close_notify();
+        self.flush()
+    }
+}
+
+//a client that sends a request a byte at a time, or stops reading the response, only holds a
+//worker until the timeout; the handle returned is for changing them later
+fn set_timeouts(stream: &TcpStream, timeout: Duration) -> io::Result<TcpStream> {
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    stream.try_clone()
+}
+
+fn serve_http(stream: TcpStream, timeout: Duration, router: &Router, limits: &Limits, streams: &Arc<Streams>) -> io::Result<()> {
+    let socket = set_timeouts(&stream, timeout)?;
+    let peer = stream.peer_addr().ok();
+    serve(stream, socket, peer, router, limits, streams)
+}
+
+fn serve_tls(
+    config: &Arc<ServerConfig>,
+    stream: TcpStream,
+    timeout: Duration,
+    router: &Router,
+    limits: &Limits,
+    streams: &Arc<Streams>,
+) -> io::Result<()> {
+    let socket = set_timeouts(&stream, timeout)?;
+    let peer = stream.peer_addr().ok();
+    serve(tls::accept(config, stream)?, socket, peer, router, limits, streams)
+}
+
+//like handle_connection, but a WebSocket or an event stream goes on to a thread of its own so
+//the pool worker is free again: they can stay open for as long as the client likes
+fn serve<S: Accepted>(
+    stream: S,
+    socket: TcpStream,
+    peer: Option<SocketAddr>,
+    router: &Router,
+    limits: &Limits,
+    streams: &Arc<Streams>,
+) -> io::Result<()> {
+    let mut stream = Buffered(BufReader::new(stream));
+    let (mut response, head) = answer(&mut stream.0, peer, router, limits);
+    let long_lived = response.upgrade.is_some() || matches!(response.body, Body::Events(_));
+    if head || !long_lived {
+        send(&mut stream, response, head)?;
+        return stream.0.get_mut().close();
+    }
+
+    let slot = match streams.open() {
+        Some(slot) => slot,
+        None => {
+            response = HttpError::new(503, "too many open streams, try again later").into();
+            send(&mut stream, response, false)?;
+            return stream.0.get_mut().close();
+        }
+    };
+    //an upgraded connection may sit quietly between messages; writes keep their timeout
+    if response.upgrade.is_some() {
+        socket.set_read_timeout(None)?;
+    }
+    thread::spawn(move || {
+        let _slot = slot;
+        if let Err(e) = send(&mut stream, response, false).and_then(|_| stream.0.get_mut().close()) {
+            eprintln!("Connection error: {}", e);
+        }
+    });
+    Ok(())
+}
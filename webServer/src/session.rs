@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::cookie::{Cookie, SameSite};
+use crate::request::Request;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Per-client state kept on the server; the client only ever holds the signed ID.
+#[derive(Debug, Clone)]
+pub struct Session {
+    id: String,
+    data: HashMap<String, String>,
+}
+
+impl Session {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.data.get(key).map(|v| v.as_str())
+    }
+
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.data.insert(key.into(), value.into());
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.data.remove(key)
+    }
+}
+
+struct Entry {
+    data: HashMap<String, String>,
+    expires: Instant,
+}
+
+/// In-memory sessions, shared by every worker behind an `Arc`.
+///
+/// The session cookie holds `<id>.<hmac>`, where the HMAC-SHA256 of the ID is keyed with
+/// the server secret. A cookie that wasn't signed by us is rejected before we even look
+/// the ID up, so IDs can't be guessed or forged. Sessions expire `ttl` after they were
+/// last saved.
+pub struct SessionStore {
+    secret: Vec<u8>,
+    ttl: Duration,
+    cookie_name: String,
+    secure: bool,
+    sessions: Mutex<HashMap<String, Entry>>,
+}
+
+impl SessionStore {
+    /// # Panics
+    ///
+    /// Panics if `secret` is shorter than 32 bytes, anything shorter is too easy to brute force.
+    pub fn new(secret: &[u8], ttl: Duration) -> SessionStore {
+        assert!(secret.len() >= 32, "session secret must be at least 32 bytes");
+        SessionStore {
+            secret: secret.to_vec(),
+            ttl,
+            cookie_name: String::from("session"),
+            secure: false,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_cookie_name(mut self, name: &str) -> SessionStore {
+        self.cookie_name = name.to_string();
+        self
+    }
+
+    /// Marks the session cookie `Secure`, so browsers only send it back over HTTPS. Turn this on
+    /// when the site is served over TLS.
+    pub fn with_secure(mut self, secure: bool) -> SessionStore {
+        self.secure = secure;
+        self
+    }
+
+    /// A fresh, empty session. It isn't stored until `save` is called.
+    pub fn create(&self) -> Session {
+        let mut bytes = [0u8; 16];
+        getrandom::getrandom(&mut bytes).expect("no OS random number generator");
+        Session {
+            id: hex(&bytes),
+            data: HashMap::new(),
+        }
+    }
+
+    /// The session belonging to `request`, if it carries a correctly signed,
+    /// unexpired session cookie.
+    pub fn load(&self, request: &Request) -> Option<Session> {
+        let value = request.cookie(&self.cookie_name)?;
+        let id = self.verify(&value)?;
+
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.get(id) {
+            Some(entry) if entry.expires > Instant::now() => Some(Session {
+                id: id.to_string(),
+                data: entry.data.clone(),
+            }),
+            Some(_) => {
+                sessions.remove(id);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Stores the session and pushes its expiry `ttl` into the future.
+    pub fn save(&self, session: &Session) {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.insert(
+            session.id.clone(),
+            Entry {
+                data: session.data.clone(),
+                expires: Instant::now() + self.ttl,
+            },
+        );
+    }
+
+    pub fn destroy(&self, session: &Session) {
+        self.sessions.lock().unwrap().remove(&session.id);
+    }
+
+    /// Drops every expired session. `load` already ignores them, this only frees the memory.
+    pub fn purge_expired(&self) {
+        let now = Instant::now();
+        self.sessions.lock().unwrap().retain(|_, entry| entry.expires > now);
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The `Set-Cookie` for `session`, to be added to the response that saved it.
+    pub fn cookie(&self, session: &Session) -> Cookie {
+        Cookie::new(self.cookie_name.clone(), self.sign(&session.id))
+            .path("/")
+            .max_age(self.ttl)
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(SameSite::Lax)
+    }
+
+    /// The `Set-Cookie` that logs the client out.
+    pub fn removal_cookie(&self) -> Cookie {
+        Cookie::removal(self.cookie_name.clone())
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any length")
+    }
+
+    fn sign(&self, id: &str) -> String {
+        let mut mac = self.mac();
+        mac.update(id.as_bytes());
+        format!("{}.{}", id, hex(&mac.finalize().into_bytes()))
+    }
+
+    //returns the ID if the signature matches; verify_slice compares in constant time
+    fn verify<'a>(&self, value: &'a str) -> Option<&'a str> {
+        let i = value.rfind('.')?;
+        let (id, signature) = (&value[..i], &value[i + 1..]);
+        let signature = unhex(signature)?;
+
+        let mut mac = self.mac();
+        mac.update(id.as_bytes());
+        mac.verify_slice(&signature).ok()?;
+        Some(id)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+//an odd length leaves a one character slice at the end, which `get` refuses
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    const SECRET: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    fn request_with(cookie: &Cookie) -> Request {
+        let mut request = Request::new("GET", "/");
+        request.headers.append("Cookie", format!("{}={}", cookie.name, cookie.value));
+        request
+    }
+
+    #[test]
+    fn round_trips_a_signed_session() {
+        let store = SessionStore::new(SECRET, Duration::from_secs(60));
+        let mut session = store.create();
+        session.insert("user", "ann");
+        store.save(&session);
+
+        let cookie = store.cookie(&session);
+        assert!(cookie.http_only);
+        assert!(!cookie.secure);
+        let loaded = store.load(&request_with(&cookie)).unwrap();
+        assert_eq!(session.id(), loaded.id());
+        assert_eq!(Some("ann"), loaded.get("user"));
+        assert!(store.with_secure(true).cookie(&session).secure);
+    }
+
+    #[test]
+    fn rejects_tampered_or_foreign_cookies() {
+        let store = SessionStore::new(SECRET, Duration::from_secs(60));
+        let session = store.create();
+        store.save(&session);
+
+        let mut forged = store.cookie(&session);
+        forged.value = format!("{}.{}", session.id(), "00".repeat(32));
+        assert!(store.load(&request_with(&forged)).is_none());
+
+        let other = SessionStore::new(b"another secret that is long enough", Duration::from_secs(60));
+        assert!(store.load(&request_with(&other.cookie(&session))).is_none());
+    }
+
+    #[test]
+    fn sessions_expire() {
+        let store = SessionStore::new(SECRET, Duration::from_millis(20));
+        let session = store.create();
+        store.save(&session);
+        let cookie = store.cookie(&session);
+
+        thread::sleep(Duration::from_millis(40));
+        assert!(store.load(&request_with(&cookie)).is_none());
+        store.purge_expired();
+        assert!(store.is_empty());
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::prelude::*;
+use std::io;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::request::Request;
+use crate::response::{Body, Response};
+
+/// One server-sent event. Only `data` is required; multi-line data is split into
+/// several `data:` lines, which the browser joins back together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub id: Option<String>,
+    pub event: Option<String>,
+    pub data: String,
+    pub retry: Option<Duration>,
+}
+
+impl Event {
+    pub fn new(data: impl Into<String>) -> Event {
+        Event {
+            id: None,
+            event: None,
+            data: data.into(),
+            retry: None,
+        }
+    }
+
+    pub fn id(mut self, id: impl Into<String>) -> Event {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// The event type, what `addEventListener` listens for on the browser side.
+    pub fn event(mut self, event: impl Into<String>) -> Event {
+        self.event = Some(event.into());
+        self
+    }
+
+    /// How long the browser waits before reconnecting after the stream drops.
+    pub fn retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+}
+
+//the wire format; CR, LF and CRLF all end a line for the browser, so the single line fields lose
+//them (and NUL, which makes an id ignored) and data is split on every one of them
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let single_line = |s: &str| s.replace(['\r', '\n', '\0'], "");
+        if let Some(event) = &self.event {
+            writeln!(f, "event: {}", single_line(event))?;
+        }
+        if let Some(id) = &self.id {
+            writeln!(f, "id: {}", single_line(id))?;
+        }
+        if let Some(retry) = self.retry {
+            writeln!(f, "retry: {}", retry.as_millis())?;
+        }
+        for line in self.data.replace("\r\n", "\n").split(['\r', '\n']) {
+            writeln!(f, "data: {}", line)?;
+        }
+        writeln!(f)
+    }
+}
+
+/// The sending half of an event stream. Cheap to clone, one per producer.
+#[derive(Clone)]
+pub struct EventSender {
+    sender: mpsc::Sender<Event>,
+}
+
+impl EventSender {
+    /// Queues an event. Fails once the client has disconnected and its stream was dropped.
+    pub fn send(&self, event: Event) -> Result<(), Event> {
+        self.sender.send(event).map_err(|e| e.0)
+    }
+}
+
+/// The body of a `text/event-stream` response: events are written as they arrive on the
+/// channel, with a comment line every `heartbeat` so proxies don't time the connection out
+/// and a vanished client is noticed.
+///
+/// Under `Server` it is written on a thread of its own, one of at most `Config::max_streams`,
+/// until every `EventSender` is dropped or the client goes away, whichever happens first.
+pub struct EventStream {
+    receiver: mpsc::Receiver<Event>,
+    backlog: Vec<Event>,
+    heartbeat: Duration,
+}
+
+/// A connected sender and stream, for a feed that belongs to a single client.
+pub fn channel() -> (EventSender, EventStream) {
+    let (sender, receiver) = mpsc::channel();
+    (
+        EventSender { sender },
+        EventStream {
+            receiver,
+            backlog: Vec::new(),
+            heartbeat: Duration::from_secs(15),
+        },
+    )
+}
+
+impl EventStream {
+    pub fn heartbeat(mut self, interval: Duration) -> EventStream {
+        self.heartbeat = interval;
+        self
+    }
+
+    pub(crate) fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for event in &self.backlog {
+            writer.write_all(event.to_string().as_bytes())?;
+        }
+        writer.flush()?;
+
+        loop {
+            match self.receiver.recv_timeout(self.heartbeat) {
+                Ok(event) => writer.write_all(event.to_string().as_bytes())?,
+                //lines starting with ':' are comments, the browser ignores them
+                Err(RecvTimeoutError::Timeout) => writer.write_all(b":\n\n")?,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+            writer.flush()?;
+        }
+    }
+}
+
+impl Response {
+    /// A `200 text/event-stream` response that streams `events` until the feed or the client ends.
+    pub fn events(events: EventStream) -> Response {
+        let mut response = Response::new(200)
+            .with_header("Content-Type", "text/event-stream")
+            .with_header("Cache-Control", "no-cache");
+        response.body = Body::Events(events);
+        response
+    }
+}
+
+struct HubState {
+    next_id: u64,
+    history: VecDeque<Event>,
+    subscribers: Vec<mpsc::Sender<Event>>,
+}
+
+/// Broadcasts events to every subscribed client and remembers the last `history` of them,
+/// so a browser reconnecting with `Last-Event-ID` gets what it missed.
+///
+/// Shared across workers behind an `Arc`. Subscribers whose client disconnected are dropped
+/// the next time something is published.
+pub struct EventHub {
+    capacity: usize,
+    heartbeat: Duration,
+    state: Mutex<HubState>,
+}
+
+impl EventHub {
+    pub fn new(history: usize) -> EventHub {
+        EventHub {
+            capacity: history,
+            heartbeat: Duration::from_secs(15),
+            state: Mutex::new(HubState {
+                next_id: 1,
+                history: VecDeque::new(),
+                subscribers: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn with_heartbeat(mut self, interval: Duration) -> EventHub {
+        self.heartbeat = interval;
+        self
+    }
+
+    /// Sends `event` to every subscriber, numbering it with the next id.
+    pub fn publish(&self, event: Event) {
+        let mut state = self.state.lock().unwrap();
+        let event = event.id(state.next_id.to_string());
+        state.next_id += 1;
+
+        state.history.push_back(event.clone());
+        while state.history.len() > self.capacity {
+            state.history.pop_front();
+        }
+        state.subscribers.retain(|s| s.send(event.clone()).is_ok());
+    }
+
+    /// A stream for `request`'s client, starting after its `Last-Event-ID` if it sent one.
+    pub fn subscribe(&self, request: &Request) -> EventStream {
+        let last_id = request
+            .header("Last-Event-ID")
+            .and_then(|id| id.trim().parse::<u64>().ok());
+
+        let (sender, receiver) = mpsc::channel();
+        let mut state = self.state.lock().unwrap();
+        let backlog = match last_id {
+            Some(last_id) => state
+                .history
+                .iter()
+                .filter(|e| e.id.as_ref().and_then(|id| id.parse::<u64>().ok()) > Some(last_id))
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+        state.subscribers.push(sender);
+
+        EventStream {
+            receiver,
+            backlog,
+            heartbeat: self.heartbeat,
+        }
+    }
+
+    pub fn subscribers(&self) -> usize {
+        self.state.lock().unwrap().subscribers.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    //accepts `limit` bytes, then fails like a socket whose peer has gone
+    struct Client {
+        written: Vec<u8>,
+        limit: usize,
+    }
+
+    impl Write for Client {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.written.len() + buf.len() > self.limit {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "client went away"));
+            }
+            self.written.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn formats_events() {
+        let event = Event::new("line one\nline two").id("7").event("update").retry(Duration::from_secs(3));
+        assert_eq!("event: update\nid: 7\nretry: 3000\ndata: line one\ndata: line two\n\n", event.to_string());
+    }
+
+    #[test]
+    fn line_breaks_cannot_add_fields() {
+        let event = Event::new("x\rid: 5\revent: admin\r\ny").id("1\r2\0").event("a\rb\nc");
+        assert_eq!("event: abc\nid: 12\ndata: x\ndata: id: 5\ndata: event: admin\ndata: y\n\n", event.to_string());
+    }
+
+    #[test]
+    fn streams_until_senders_are_dropped() {
+        let (sender, events) = channel();
+        let events = events.heartbeat(Duration::from_millis(10));
+        let producer = thread::spawn(move || {
+            sender.send(Event::new("first")).unwrap();
+            thread::sleep(Duration::from_millis(30));
+            sender.send(Event::new("second")).unwrap();
+        });
+
+        let mut out = Vec::new();
+        Response::events(events).write_to(&mut out).unwrap();
+        producer.join().unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(out.contains("Content-Type: text/event-stream\r\n"));
+        assert!(!out.contains("Content-Length"));
+        let body = &out[out.find("\r\n\r\n").unwrap() + 4..];
+        assert!(body.starts_with("data: first\n\n"));
+        assert!(body.contains(":\n\n"), "no heartbeat in {:?}", body);
+        assert!(body.ends_with("data: second\n\n"));
+    }
+
+    #[test]
+    fn resumes_after_last_event_id() {
+        let hub = EventHub::new(2);
+        for data in &["a", "b", "c"] {
+            hub.publish(Event::new(*data));
+        }
+
+        let mut request = Request::new("GET", "/feed");
+        request.headers.append("Last-Event-ID", "1");
+        let stream = hub.subscribe(&request);
+        //"a" (id 1) was already seen and only two events are kept anyway
+        let ids: Vec<_> = stream.backlog.iter().map(|e| e.id.clone().unwrap()).collect();
+        assert_eq!(vec!["2", "3"], ids);
+
+        let fresh = hub.subscribe(&Request::new("GET", "/feed"));
+        assert!(fresh.backlog.is_empty());
+    }
+
+    #[test]
+    fn drops_subscribers_that_disconnect() {
+        let hub = EventHub::new(10).with_heartbeat(Duration::from_millis(5));
+        let stream = hub.subscribe(&Request::new("GET", "/feed"));
+        assert_eq!(1, hub.subscribers());
+
+        //the client takes the response head, then hangs up
+        let mut client = Client { written: Vec::new(), limit: 120 };
+        assert!(Response::events(stream).write_to(&mut client).is_err());
+
+        hub.publish(Event::new("anyone there?"));
+        assert_eq!(0, hub.subscribers());
+    }
+}
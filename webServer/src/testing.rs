@@ -0,0 +1,349 @@
+//! Helpers for testing handlers without a browser.
+//!
+//! `TestClient` runs requests through a `Router` (rate limits included) in the same thread
+//! and hands back the `Response`, no socket involved. `TestServer` is for the end-to-end
+//! cases: a real `Server` on a free loopback port, talked to over TCP.
+//!
+//! ```
+//! use webServer::testing::TestClient;
+//! use webServer::{Request, Response, Router};
+//!
+//! let client = TestClient::new(Router::new().get("/", |_: &Request| Ok(Response::text(200, "hi"))));
+//! let response = client.get("/");
+//! assert_eq!(200, response.status);
+//! assert_eq!(b"hi", response.body.as_bytes());
+//! ```
+
+use std::io::prelude::*;
+use std::io::{self, BufReader, Cursor};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::thread;
+
+use crate::config::Config;
+use crate::proxy::read_response_head;
+use crate::reload::Swap;
+use crate::request::{Limits, Request};
+use crate::response::{Body, Response};
+use crate::router::Router;
+use crate::server::{handle_connection, Server};
+
+//the address requests made through the client appear to come from
+const LOOPBACK: &str = "127.0.0.1:50000";
+
+pub struct TestClient {
+    router: Router,
+    limits: Limits,
+}
+
+impl TestClient {
+    pub fn new(router: Router) -> TestClient {
+        TestClient {
+            router,
+            limits: Limits::default(),
+        }
+    }
+
+    /// Limits applied by `raw`, which parses requests like the server does.
+    pub fn with_limits(mut self, limits: Limits) -> TestClient {
+        self.limits = limits;
+        self
+    }
+
+    pub fn get(&self, target: &str) -> Response {
+        self.request("GET", target).send()
+    }
+
+    pub fn post(&self, target: &str, body: impl Into<Vec<u8>>) -> Response {
+        self.request("POST", target).body(body).send()
+    }
+
+    /// A request to fill in before sending. It comes from 127.0.0.1 unless `peer` says otherwise.
+    pub fn request(&self, method: &str, target: &str) -> TestRequest<'_> {
+        let mut request = Request::new(method, target);
+        request.peer = LOOPBACK.parse().ok();
+        TestRequest { client: self, request }
+    }
+
+    /// Feeds `bytes` to `handle_connection` as if a client had sent them, and parses what it
+    /// writes back. Useful for what the router never sees, such as malformed requests.
+    pub fn raw(&self, bytes: &[u8]) -> io::Result<Response> {
+        let mut connection = Memory {
+            input: Cursor::new(bytes.to_vec()),
+            output: Vec::new(),
+        };
+        handle_connection(&mut connection, LOOPBACK.parse().ok(), &self.router, &self.limits)?;
+        parse_response(&connection.output[..])
+    }
+}
+
+pub struct TestRequest<'a> {
+    client: &'a TestClient,
+    request: Request,
+}
+
+impl<'a> TestRequest<'a> {
+    pub fn header(mut self, name: &str, value: &str) -> TestRequest<'a> {
+        self.request.headers.append(name, value);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> TestRequest<'a> {
+        self.request.body = body.into();
+        self.request.headers.set("Content-Length", self.request.body.len().to_string());
+        self
+    }
+
+    pub fn peer(mut self, peer: SocketAddr) -> TestRequest<'a> {
+        self.request.peer = Some(peer);
+        self
+    }
+
+    pub fn send(self) -> Response {
+        self.client.router.dispatch(&self.request)
+    }
+}
+
+/// Reads a response's whole body, whatever its kind. An event stream is read until its feed ends.
+pub fn read_body(response: &mut Response) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    match &mut response.body {
+        Body::Bytes(bytes) => out.extend_from_slice(bytes),
+        Body::Shared(bytes) => out.extend_from_slice(bytes),
+        Body::Stream { reader, .. } => {
+            reader.read_to_end(&mut out)?;
+        }
+        Body::Events(_) => {
+            let mut written = Vec::new();
+            response.write_to(&mut written)?;
+            let start = written.windows(4).position(|w| w == b"\r\n\r\n").map_or(0, |i| i + 4);
+            out.extend_from_slice(&written[start..]);
+        }
+    }
+    Ok(out)
+}
+
+/// A `Server` running in the background on a port picked by the OS.
+///
+/// The server thread is not stopped when this is dropped; it ends with the test binary.
+pub struct TestServer {
+    addr: SocketAddr,
+    routes: Arc<Swap<Router>>,
+}
+
+impl TestServer {
+    pub fn spawn(router: Router) -> io::Result<TestServer> {
+        TestServer::spawn_with(Config::default(), router)
+    }
+
+    /// Like `spawn`, but with the rest of `config`; only its address is replaced.
+    pub fn spawn_with(config: Config, router: Router) -> io::Result<TestServer> {
+        let config = Config {
+            address: String::from("127.0.0.1:0"),
+            ..config
+        };
+        let server = Server::bind(&config, router)?;
+        let addr = server.local_addr()?;
+        let routes = server.routes();
+        thread::spawn(move || server.run());
+        Ok(TestServer { addr, routes })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The running server's router, to swap in another one.
+    pub fn routes(&self) -> Arc<Swap<Router>> {
+        Arc::clone(&self.routes)
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    pub fn connect(&self) -> io::Result<TcpStream> {
+        TcpStream::connect(self.addr)
+    }
+
+    pub fn get(&self, target: &str) -> io::Result<Response> {
+        self.send(format!("GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", target, self.addr).as_bytes())
+    }
+
+    /// Sends `bytes` on a fresh connection and reads the response until the server closes it.
+    pub fn send(&self, bytes: &[u8]) -> io::Result<Response> {
+        let mut stream = self.connect()?;
+        stream.write_all(bytes)?;
+        stream.shutdown(Shutdown::Write)?;
+        parse_response(BufReader::new(stream))
+    }
+}
+
+//every response we write ends with the connection, so the body is whatever follows the head
+fn parse_response<R: BufRead>(mut reader: R) -> io::Result<Response> {
+    let (status, headers) = read_response_head(&mut reader)?;
+    let mut body = Vec::new();
+    reader.read_to_end(&mut body)?;
+    let mut response = Response::new(status).with_body(body);
+    response.headers = headers;
+    Ok(response)
+}
+
+struct Memory {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl Read for Memory {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for Memory {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errorpages::ErrorPages;
+    use crate::files::FileCache;
+    use crate::ratelimit::RateLimiter;
+    use crate::sse::EventHub;
+    use crate::websocket::WebSocketHandler;
+    use std::time::{Duration, Instant};
+
+    fn router() -> Router {
+        Router::new()
+            .get("/who", |r: &Request| Ok(Response::text(200, r.peer.unwrap().ip().to_string())))
+            .post("/echo", |r: &Request| Ok(Response::new(200).with_body(r.body.clone())))
+    }
+
+    #[test]
+    fn drives_the_router_in_process() {
+        let client = TestClient::new(router().limit("/echo", RateLimiter::new(1.0, 1)));
+        assert_eq!(b"127.0.0.1", client.get("/who").body.as_bytes());
+        assert_eq!(b"ping", client.post("/echo", "ping").body.as_bytes());
+        assert_eq!(429, client.post("/echo", "again").status);
+
+        let elsewhere = client.request("POST", "/echo").peer("192.0.2.1:1".parse().unwrap()).body("hi").send();
+        assert_eq!(200, elsewhere.status);
+    }
+
+    #[test]
+    fn raw_requests_go_through_the_parser() {
+        let client = TestClient::new(router());
+        let response = client.raw(b"POST /echo HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc").unwrap();
+        assert_eq!(b"abc", response.body.as_bytes());
+        assert_eq!(Some("3"), response.headers.get("Content-Length"));
+
+        assert_eq!(505, client.raw(b"GET / HTTP/2.0\r\n\r\n").unwrap().status);
+
+        //the length a GET would get, and nothing after the head
+        let response = client.raw(b"HEAD /who HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(Some("9"), response.headers.get("Content-Length"));
+        assert!(response.body.as_bytes().is_empty());
+    }
+
+    #[test]
+    fn unreadable_requests_get_the_error_pages() {
+        let pages = Arc::new(FileCache::new(std::env::temp_dir().join("webserver-no-pages")));
+        let client = TestClient::new(router().errors(ErrorPages::new(pages)))
+            .with_limits(Limits { max_body: 4, ..Limits::default() });
+        let html = Some("text/html; charset=utf-8");
+
+        let bad = client.raw(b"GET / HTTP/2.0\r\n\r\n").unwrap();
+        assert_eq!((505, html), (bad.status, bad.headers.get("Content-Type")));
+        let large = client.raw(b"POST /echo HTTP/1.1\r\nContent-Length: 10\r\n\r\n0123456789").unwrap();
+        assert_eq!((413, html), (large.status, large.headers.get("Content-Type")));
+    }
+
+    #[test]
+    fn serves_over_loopback() {
+        let server = TestServer::spawn(router()).unwrap();
+        assert!(server.url("/who").starts_with("http://127.0.0.1:"));
+        let mut response = server.get("/who").unwrap();
+        assert_eq!(200, response.status);
+        assert_eq!(b"127.0.0.1".to_vec(), read_body(&mut response).unwrap());
+
+        server.routes().store(Router::new().get("/who", |_: &Request| Ok(Response::text(200, "reloaded"))));
+        assert_eq!(b"reloaded", server.get("/who").unwrap().body.as_bytes());
+    }
+
+    #[test]
+    fn connect_hands_over_the_raw_connection() {
+        let router = Router::new().get(
+            "/echo",
+            WebSocketHandler::new(|mut ws| {
+                if let Ok(message) = ws.read() {
+                    ws.send(&message).unwrap();
+                }
+            }),
+        );
+        let server = TestServer::spawn(router).unwrap();
+        let mut stream = server.connect().unwrap();
+        stream
+            .write_all(
+                b"GET /echo HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            )
+            .unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let (status, headers) = read_response_head(&mut reader).unwrap();
+        assert_eq!(101, status);
+        assert_eq!(Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="), headers.get("Sec-WebSocket-Accept"));
+
+        //a masked text frame "hi", echoed back unmasked
+        stream.write_all(&[0x81, 0x82, 1, 2, 3, 4, b'h' ^ 1, b'i' ^ 2]).unwrap();
+        let mut echoed = [0; 4];
+        reader.read_exact(&mut echoed).unwrap();
+        assert_eq!([0x81, 2, b'h', b'i'], echoed);
+    }
+
+    #[test]
+    fn event_streams_leave_the_pool_free() {
+        let hub = Arc::new(EventHub::new(8));
+        let events = Arc::clone(&hub);
+        let router = router().get("/events", move |r: &Request| Ok(Response::events(events.subscribe(r))));
+        let config = Config { threads: 1, max_streams: 1, ..Config::default() };
+        let server = TestServer::spawn_with(config, router).unwrap();
+
+        let stream = server.connect().unwrap();
+        (&stream).write_all(b"GET /events HTTP/1.1\r\n\r\n").unwrap();
+        let (status, _) = read_response_head(&mut BufReader::new(&stream)).unwrap();
+        assert_eq!(200, status);
+
+        //the only worker answers other requests while the stream stays open, and a second
+        //stream is turned away instead of taking it
+        assert_eq!(200, server.get("/who").unwrap().status);
+        assert_eq!(503, server.get("/events").unwrap().status);
+    }
+
+    #[test]
+    fn slow_clients_are_dropped() {
+        let config = Config { timeout: Duration::from_secs(1), ..Config::default() };
+        let server = TestServer::spawn_with(config, router()).unwrap();
+        let mut stream = server.connect().unwrap();
+        stream.write_all(b"GET /who HT").unwrap();
+
+        let started = Instant::now();
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn rate_limits_come_before_the_body() {
+        let client = TestClient::new(router().limit("/echo", RateLimiter::new(0.001, 1)));
+        assert_eq!(200, client.raw(b"POST /echo HTTP/1.1\r\nContent-Length: 2\r\n\r\nhi").unwrap().status);
+        //over the limit: answered without waiting for a body that is never sent
+        let response = client.raw(b"POST /echo HTTP/1.1\r\nContent-Length: 100000000\r\n\r\n").unwrap();
+        assert_eq!(429, response.status);
+    }
+}
@@ -0,0 +1,146 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+use crate::request::Request;
+use crate::response::Response;
+
+/// A TCP connection wrapped in TLS. The handshake runs on the first read or write,
+/// so it happens on the pool worker rather than on the accepting thread.
+pub type TlsStream = StreamOwned<ServerConnection, TcpStream>;
+
+/// Builds the rustls server config from a PEM certificate chain and a PEM private key
+/// (PKCS#8, PKCS#1 or SEC1).
+pub fn load_tls_config(cert_path: &Path, key_path: &Path) -> io::Result<Arc<ServerConfig>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?)).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(invalid(format!("no certificate found in {}", cert_path.display())));
+    }
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key_path)?))?
+        .ok_or_else(|| invalid(format!("no private key found in {}", key_path.display())))?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| invalid(e.to_string()))?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| invalid(e.to_string()))?;
+    Ok(Arc::new(config))
+}
+
+pub fn accept(config: &Arc<ServerConfig>, stream: TcpStream) -> io::Result<TlsStream> {
+    let connection = ServerConnection::new(Arc::clone(config)).map_err(|e| invalid(e.to_string()))?;
+    Ok(StreamOwned::new(connection, stream))
+}
+
+/// Sends the client to the same target on the HTTPS listener, for the plaintext port in redirect mode.
+pub fn https_redirect(request: &Request, https_port: u16) -> Response {
+    let host = request.header("Host").unwrap_or("localhost");
+    //strip the plaintext port, but don't mistake the colons of an IPv6 literal for one
+    let host = match host.rfind(':') {
+        Some(i) if !host[i..].contains(']') => &host[..i],
+        _ => host,
+    };
+    let port = if https_port == 443 { String::new() } else { format!(":{}", https_port) };
+    let target = if request.target.starts_with('/') { request.target.as_str() } else { request.path.as_str() };
+
+    //308 rather than 301 so a POST stays a POST
+    Response::new(308).with_header("Location", format!("https://{}{}{}", host, port, target))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, TlsSettings};
+    use crate::router::Router;
+    use crate::server::Server;
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConfig, ClientConnection, RootCertStore};
+    use std::convert::TryFrom;
+    use std::fs;
+    use std::io::prelude::*;
+    use std::path::PathBuf;
+    use std::thread;
+
+    //self-signed certificate for "localhost", written to a fresh temporary directory
+    fn self_signed(name: &str) -> (PathBuf, PathBuf, Vec<u8>) {
+        let dir = std::env::temp_dir().join(format!("webserver-tls-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let certified = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+        let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        fs::write(&cert, certified.cert.pem()).unwrap();
+        fs::write(&key, certified.key_pair.serialize_pem()).unwrap();
+        (cert, key, certified.cert.der().to_vec())
+    }
+
+    fn start(cert: PathBuf, key: PathBuf, redirect_http: bool) -> Server {
+        let config = Config {
+            address: String::from("127.0.0.1:0"),
+            tls: Some(TlsSettings {
+                address: String::from("127.0.0.1:0"),
+                cert,
+                key,
+            }),
+            redirect_http,
+            ..Config::default()
+        };
+        let router = Router::new().get("/", |_: &Request| Ok(Response::text(200, "secret page")));
+        Server::bind(&config, router).unwrap()
+    }
+
+    #[test]
+    fn serves_requests_over_tls() {
+        let (cert, key, der) = self_signed("serve");
+        let server = start(cert, key, false);
+        let addr = server.tls_local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let mut roots = RootCertStore::empty();
+        roots.add(der.into()).unwrap();
+        let client = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connection = ClientConnection::new(Arc::new(client), ServerName::try_from("localhost").unwrap()).unwrap();
+        let mut stream = StreamOwned::new(connection, TcpStream::connect(addr).unwrap());
+
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("secret page"));
+    }
+
+    #[test]
+    fn redirects_plain_http() {
+        let (cert, key, _) = self_signed("redirect");
+        let server = start(cert, key, true);
+        let (http, https) = (server.local_addr().unwrap(), server.tls_local_addr().unwrap());
+        thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(http).unwrap();
+        stream.write_all(b"POST /form?a=1 HTTP/1.1\r\nHost: localhost:7878\r\nContent-Length: 0\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 308 Permanent Redirect\r\n"));
+        let location = format!("Location: https://localhost:{}/form?a=1\r\n", https.port());
+        assert!(response.contains(&location), "{}", response);
+    }
+
+    #[test]
+    fn missing_key_is_an_error() {
+        let (cert, _, _) = self_signed("nokey");
+        assert!(load_tls_config(&cert, &cert).is_err());
+    }
+}
@@ -0,0 +1,208 @@
+use crate::error::HttpError;
+
+/// Decoded name/value pairs from a query string or an urlencoded form body.
+///
+/// Both use the same `a=1&b=2` format, and a name may appear more than once
+/// (`?tag=a&tag=b`), so values are kept in the order they were sent.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Params {
+    pairs: Vec<(String, String)>,
+}
+
+impl Params {
+    pub fn new() -> Params {
+        Params { pairs: Vec::new() }
+    }
+
+    /// Parses `a=1&b=two+words&c=%E2%9C%93`. `+` means a space here.
+    pub fn parse(input: &str) -> Result<Params, HttpError> {
+        let mut params = Params::new();
+        for pair in input.split('&').filter(|p| !p.is_empty()) {
+            let (name, value) = match pair.find('=') {
+                Some(i) => (&pair[..i], &pair[i + 1..]),
+                None => (pair, ""),
+            };
+            params.push(decode(name, true)?, decode(value, true)?);
+        }
+        Ok(params)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.pairs.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.pairs.iter().filter(move |(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    pub fn push(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.pairs.push((name.into(), value.into()));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+/// Splits a request target into its canonical path and its query parameters.
+///
+/// The path is percent-decoded and has its `.` and `..` segments resolved, so
+/// `/a/./b/../%63` becomes `/a/c`. Absolute-form targets (`http://host/a`) are reduced
+/// to their path, as a server must accept them even though browsers don't send them.
+pub fn split_target(target: &str) -> Result<(String, Params), HttpError> {
+    if target == "*" {
+        return Ok((String::from("*"), Params::new()));
+    }
+
+    //fragments are never sent by a well behaved client, but drop one if it shows up
+    let target = target.split('#').next().unwrap_or("");
+    let (path, query) = match target.find('?') {
+        Some(i) => (&target[..i], &target[i + 1..]),
+        None => (target, ""),
+    };
+    let path = match path.find("://") {
+        Some(i) => match path[i + 3..].find('/') {
+            Some(j) => &path[i + 3 + j..],
+            None => "/",
+        },
+        None => path,
+    };
+    if !path.starts_with('/') {
+        return Err(HttpError::bad_request("request target must start with '/'"));
+    }
+
+    let path = decode(path, false)?;
+    //a decoded NUL would cut file names short once they reach the OS
+    if path.contains('\0') {
+        return Err(HttpError::bad_request("request path contains NUL"));
+    }
+
+    Ok((remove_dot_segments(&path), Params::parse(query)?))
+}
+
+/// Percent-decodes `input`. The decoded bytes must be valid UTF-8, so a multi-byte
+/// character sent as `%C3%A9` comes back as `é`. With `plus_as_space`, `+` decodes to
+/// a space as it does in query strings; in paths it is a literal `+`.
+pub fn decode(input: &str, plus_as_space: bool) -> Result<String, HttpError> {
+    //nothing to do for the common case, skip the copy
+    if !(input.contains('%') || plus_as_space && input.contains('+')) {
+        return Ok(input.to_string());
+    }
+
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' if plus_as_space => out.push(b' '),
+            b'%' => {
+                let byte = bytes
+                    .get(i + 1..i + 3)
+                    .and_then(|h| Some((hex_value(h[0])? << 4) | hex_value(h[1])?))
+                    .ok_or_else(|| HttpError::bad_request("invalid percent-encoding"))?;
+                out.push(byte);
+                i += 2;
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8(out).map_err(|_| HttpError::bad_request("percent-encoded bytes are not valid UTF-8"))
+}
+
+/// Percent-encodes everything except RFC 3986 unreserved characters and `/`,
+/// so the result can be dropped into a path, e.g. for links and redirects.
+pub fn encode_path(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for &b in input.as_bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => out.push(b as char),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+//RFC 3986 section 5.2.4, for a path that always starts with '/'
+fn remove_dot_segments(path: &str) -> String {
+    let segments: Vec<&str> = path.split('/').skip(1).collect();
+    let last = segments.len().saturating_sub(1);
+    let mut out: Vec<&str> = Vec::new();
+
+    for (i, segment) in segments.iter().enumerate() {
+        match *segment {
+            "." => {}
+            ".." => {
+                out.pop();
+            }
+            s => out.push(s),
+        }
+        //"/a/b/.." names the directory /a/, keep its trailing slash
+        if i == last && (*segment == "." || *segment == "..") {
+            out.push("");
+        }
+    }
+
+    format!("/{}", out.join("/"))
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_paths() {
+        assert_eq!("/", split_target("/").unwrap().0);
+        assert_eq!("/a/c", split_target("/a/./b/../c").unwrap().0);
+        assert_eq!("/a/", split_target("/a/b/..").unwrap().0);
+        assert_eq!("/etc/passwd", split_target("/../../etc/passwd").unwrap().0);
+        assert_eq!("/x", split_target("/a/%2e%2E/x").unwrap().0);
+        assert_eq!("/a+b/café", split_target("/a+b/caf%C3%A9").unwrap().0);
+        assert_eq!("/p", split_target("http://localhost:7878/p?x=1").unwrap().0);
+    }
+
+    #[test]
+    fn decodes_multi_valued_query() {
+        let (path, query) = split_target("/search?q=rust+book&tag=a&tag=b%26c&flag&e=%E2%9C%93").unwrap();
+
+        assert_eq!("/search", path);
+        assert_eq!(Some("rust book"), query.get("q"));
+        assert_eq!(vec!["a", "b&c"], query.get_all("tag").collect::<Vec<_>>());
+        assert_eq!(Some(""), query.get("flag"));
+        assert_eq!(Some("✓"), query.get("e"));
+        assert_eq!(None, query.get("missing"));
+    }
+
+    #[test]
+    fn rejects_bad_encodings() {
+        assert_eq!(400, split_target("/a%2").unwrap_err().status());
+        assert_eq!(400, split_target("/a%zz").unwrap_err().status());
+        //a lone continuation byte is not UTF-8
+        assert_eq!(400, split_target("/a%80").unwrap_err().status());
+        assert_eq!(400, split_target("/a%00b").unwrap_err().status());
+        assert_eq!(400, split_target("relative").unwrap_err().status());
+    }
+
+    #[test]
+    fn encodes_paths() {
+        assert_eq!("/my%20docs/caf%C3%A9.txt", encode_path("/my docs/café.txt"));
+        assert_eq!("/my docs/café.txt", decode(&encode_path("/my docs/café.txt"), false).unwrap());
+    }
+}
@@ -0,0 +1,541 @@
+use std::io::prelude::*;
+use std::io;
+use std::sync::Arc;
+
+use base64::Engine;
+use sha1::{Digest, Sha1};
+
+use crate::error::HttpError;
+use crate::request::Request;
+use crate::response::Response;
+use crate::router::Handler;
+use crate::server::Connection;
+
+//fixed by RFC 6455, mixed into the handshake key so a non-websocket server can't accidentally agree
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// The status code and reason from the close frame, if it carried one.
+    Close(Option<(u16, String)>),
+}
+
+/// Which end of the connection we are. Clients must mask every frame they send and
+/// servers must not, so each side rejects frames that get this wrong.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Server,
+    Client,
+}
+
+/// A WebSocket connection over any byte stream, after the handshake has completed.
+pub struct WebSocket<S> {
+    stream: S,
+    role: Role,
+    max_message: usize,
+    close_sent: bool,
+    //a fragmented message being assembled; kept here since control frames may arrive in between
+    fragments: Option<(u8, Vec<u8>)>,
+}
+
+impl<S: Read + Write> WebSocket<S> {
+    pub fn new(stream: S, role: Role) -> WebSocket<S> {
+        WebSocket {
+            stream,
+            role,
+            max_message: 16 * 1024 * 1024,
+            close_sent: false,
+            fragments: None,
+        }
+    }
+
+    /// Largest message we assemble from fragments before closing with 1009 (message too big).
+    pub fn set_max_message(&mut self, bytes: usize) {
+        self.max_message = bytes;
+    }
+
+    /// Reads the next complete message, reassembling fragments.
+    ///
+    /// Pings are answered with a pong automatically and close frames are echoed, but both are
+    /// still returned so the caller can see them. After a `Close` the caller should stop reading.
+    pub fn read(&mut self) -> io::Result<Message> {
+        loop {
+            let (fin, opcode, payload) = self.read_frame()?;
+            match opcode {
+                OP_TEXT | OP_BINARY => {
+                    if self.fragments.is_some() {
+                        return Err(self.fail(1002, "new message started inside a fragmented one"));
+                    }
+                    if fin {
+                        return self.finish(opcode, payload);
+                    }
+                    self.fragments = Some((opcode, payload));
+                }
+                OP_CONTINUATION => {
+                    let (first_opcode, mut data) = match self.fragments.take() {
+                        Some(started) => started,
+                        None => return Err(self.fail(1002, "continuation frame without a message")),
+                    };
+                    if data.len() + payload.len() > self.max_message {
+                        return Err(self.fail(1009, "message too big"));
+                    }
+                    data.extend_from_slice(&payload);
+                    if fin {
+                        return self.finish(first_opcode, data);
+                    }
+                    self.fragments = Some((first_opcode, data));
+                }
+                OP_PING => {
+                    self.write_frame(true, OP_PONG, &payload)?;
+                    return Ok(Message::Ping(payload));
+                }
+                OP_PONG => return Ok(Message::Pong(payload)),
+                OP_CLOSE => {
+                    let close = match payload.len() {
+                        0 => None,
+                        1 => return Err(self.fail(1002, "close frame with a one byte payload")),
+                        _ => {
+                            let code = u16::from_be_bytes([payload[0], payload[1]]);
+                            let reason = String::from_utf8_lossy(&payload[2..]).into_owned();
+                            Some((code, reason))
+                        }
+                    };
+                    if !self.close_sent {
+                        self.close_sent = true;
+                        self.write_frame(true, OP_CLOSE, &payload[..payload.len().min(2)])?;
+                    }
+                    return Ok(Message::Close(close));
+                }
+                _ => return Err(self.fail(1002, "unknown opcode")),
+            }
+        }
+    }
+
+    pub fn send(&mut self, message: &Message) -> io::Result<()> {
+        match message {
+            Message::Text(text) => self.write_frame(true, OP_TEXT, text.as_bytes()),
+            Message::Binary(data) => self.write_frame(true, OP_BINARY, data),
+            Message::Ping(data) => self.write_frame(true, OP_PING, data),
+            Message::Pong(data) => self.write_frame(true, OP_PONG, data),
+            Message::Close(close) => {
+                let (code, reason) = match close {
+                    Some((code, reason)) => (*code, reason.as_str()),
+                    None => (1000, ""),
+                };
+                self.close(code, reason)
+            }
+        }
+    }
+
+    /// Sends a text or binary message split into frames of at most `fragment_size` bytes.
+    pub fn send_fragmented(&mut self, message: &Message, fragment_size: usize) -> io::Result<()> {
+        assert!(fragment_size > 0);
+        let (opcode, data) = match message {
+            Message::Text(text) => (OP_TEXT, text.as_bytes()),
+            Message::Binary(data) => (OP_BINARY, data.as_slice()),
+            _ => return self.send(message),
+        };
+
+        let mut chunks = data.chunks(fragment_size).peekable();
+        let mut opcode = opcode;
+        if chunks.peek().is_none() {
+            return self.write_frame(true, opcode, &[]);
+        }
+        while let Some(chunk) = chunks.next() {
+            self.write_frame(chunks.peek().is_none(), opcode, chunk)?;
+            opcode = OP_CONTINUATION;
+        }
+        Ok(())
+    }
+
+    /// Starts the closing handshake; keep reading until the peer's `Close` comes back.
+    pub fn close(&mut self, code: u16, reason: &str) -> io::Result<()> {
+        if self.close_sent {
+            return Ok(());
+        }
+        self.close_sent = true;
+        let mut payload = code.to_be_bytes().to_vec();
+        payload.extend_from_slice(reason.as_bytes());
+        self.write_frame(true, OP_CLOSE, &payload)
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    fn finish(&mut self, opcode: u8, data: Vec<u8>) -> io::Result<Message> {
+        if opcode == OP_BINARY {
+            return Ok(Message::Binary(data));
+        }
+        match String::from_utf8(data) {
+            Ok(text) => Ok(Message::Text(text)),
+            Err(_) => Err(self.fail(1007, "text message is not valid UTF-8")),
+        }
+    }
+
+    fn read_frame(&mut self) -> io::Result<(bool, u8, Vec<u8>)> {
+        let mut head = [0u8; 2];
+        self.stream.read_exact(&mut head)?;
+        let fin = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0F;
+        let masked = head[1] & 0x80 != 0;
+
+        //we negotiate no extensions, so the reserved bits must stay zero
+        if head[0] & 0x70 != 0 {
+            return Err(self.fail(1002, "reserved bits set"));
+        }
+        if masked != (self.role == Role::Server) {
+            return Err(self.fail(1002, "frame masking is wrong for this side of the connection"));
+        }
+
+        let len = match head[1] & 0x7F {
+            126 => {
+                let mut len = [0u8; 2];
+                self.stream.read_exact(&mut len)?;
+                u16::from_be_bytes(len) as u64
+            }
+            127 => {
+                let mut len = [0u8; 8];
+                self.stream.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => len as u64,
+        };
+        if opcode & 0x8 != 0 && (len > 125 || !fin) {
+            return Err(self.fail(1002, "control frames must be short and unfragmented"));
+        }
+        if len > self.max_message as u64 {
+            return Err(self.fail(1009, "message too big"));
+        }
+
+        let mut mask = [0u8; 4];
+        if masked {
+            self.stream.read_exact(&mut mask)?;
+        }
+        let mut payload = vec![0u8; len as usize];
+        self.stream.read_exact(&mut payload)?;
+        if masked {
+            apply_mask(&mut payload, mask);
+        }
+
+        Ok((fin, opcode, payload))
+    }
+
+    fn write_frame(&mut self, fin: bool, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(payload.len() + 14);
+        frame.push(if fin { 0x80 } else { 0 } | opcode);
+
+        let mask_bit = if self.role == Role::Client { 0x80 } else { 0 };
+        match payload.len() {
+            len if len < 126 => frame.push(mask_bit | len as u8),
+            len if len <= u16::MAX as usize => {
+                frame.push(mask_bit | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(mask_bit | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+
+        if self.role == Role::Client {
+            let mut mask = [0u8; 4];
+            getrandom::getrandom(&mut mask).map_err(|e| io::Error::other(e.to_string()))?;
+            frame.extend_from_slice(&mask);
+            let start = frame.len();
+            frame.extend_from_slice(payload);
+            apply_mask(&mut frame[start..], mask);
+        } else {
+            frame.extend_from_slice(payload);
+        }
+
+        self.stream.write_all(&frame)?;
+        self.stream.flush()
+    }
+
+    //sends a close frame with `code` (best effort, the peer may be gone) and returns the error to report
+    fn fail(&mut self, code: u16, reason: &str) -> io::Error {
+        let _ = self.close(code, reason);
+        io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+    }
+}
+
+fn apply_mask(data: &mut [u8], mask: [u8; 4]) {
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+}
+
+/// The `Sec-WebSocket-Accept` value answering a client's `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(HANDSHAKE_GUID.as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(sha1.finalize())
+}
+
+/// Checks that `request` is a valid RFC 6455 opening handshake and answers it with
+/// `101 Switching Protocols`. Once that is written, `session` runs with the socket.
+///
+/// Under `Server` the session runs on a thread of its own, one of at most
+/// `Config::max_streams`, until `session` returns.
+pub fn upgrade<F>(request: &Request, session: F) -> Result<Response, HttpError>
+where
+    F: FnOnce(WebSocket<&mut dyn Connection>) + Send + 'static,
+{
+    if request.method != "GET" {
+        return Err(HttpError::new(405, "websocket handshake must be a GET"));
+    }
+    let has_token = |header: &str, token: &str| {
+        request
+            .headers
+            .get_all(header)
+            .flat_map(|v| v.split(','))
+            .any(|v| v.trim().eq_ignore_ascii_case(token))
+    };
+    if !has_token("Upgrade", "websocket") || !has_token("Connection", "upgrade") {
+        return Err(HttpError::bad_request("not a websocket upgrade request"));
+    }
+    if request.header("Sec-WebSocket-Version") != Some("13") {
+        return Ok(Response::text(426, "only websocket version 13 is supported\n")
+            .with_header("Sec-WebSocket-Version", "13"));
+    }
+    let key = request
+        .header("Sec-WebSocket-Key")
+        .filter(|key| {
+            base64::engine::general_purpose::STANDARD
+                .decode(key)
+                .map(|k| k.len() == 16)
+                .unwrap_or(false)
+        })
+        .ok_or_else(|| HttpError::bad_request("missing or invalid Sec-WebSocket-Key"))?;
+
+    Ok(Response::new(101)
+        .with_header("Upgrade", "websocket")
+        .with_header("Connection", "Upgrade")
+        .with_header("Sec-WebSocket-Accept", accept_key(key))
+        .with_upgrade(move |stream: &mut dyn Connection| session(WebSocket::new(stream, Role::Server))))
+}
+
+/// A route handler that upgrades every request to a WebSocket and runs `session` on it.
+///
+/// ```no_run
+/// use webServer::{Router, websocket::{Message, WebSocketHandler}};
+///
+/// let router = Router::new().get("/echo", WebSocketHandler::new(|mut ws| {
+///     while let Ok(message) = ws.read() {
+///         match message {
+///             Message::Text(_) | Message::Binary(_) => { let _ = ws.send(&message); }
+///             Message::Close(_) => break,
+///             _ => {}
+///         }
+///     }
+/// }));
+/// ```
+pub struct WebSocketHandler<F> {
+    session: Arc<F>,
+}
+
+impl<F> WebSocketHandler<F>
+where
+    F: Fn(WebSocket<&mut dyn Connection>) + Send + Sync + 'static,
+{
+    pub fn new(session: F) -> WebSocketHandler<F> {
+        WebSocketHandler {
+            session: Arc::new(session),
+        }
+    }
+}
+
+impl<F> Handler for WebSocketHandler<F>
+where
+    F: Fn(WebSocket<&mut dyn Connection>) + Send + Sync + 'static,
+{
+    fn handle(&self, request: &Request) -> Result<Response, HttpError> {
+        let session = Arc::clone(&self.session);
+        upgrade(request, move |ws| session(ws))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::router::Router;
+    use crate::server::Server;
+    use std::io::{BufRead, BufReader, Cursor};
+    use std::net::TcpStream;
+    use std::thread;
+
+    //frames written by one side, replayed into the other
+    struct Duplex {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Duplex {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Duplex {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn sent_by(role: Role, send: impl FnOnce(&mut WebSocket<Duplex>)) -> Vec<u8> {
+        let mut ws = WebSocket::new(Duplex { input: Cursor::new(Vec::new()), output: Vec::new() }, role);
+        send(&mut ws);
+        ws.stream.output
+    }
+
+    fn receiver(role: Role, bytes: Vec<u8>) -> WebSocket<Duplex> {
+        WebSocket::new(Duplex { input: Cursor::new(bytes), output: Vec::new() }, role)
+    }
+
+    #[test]
+    fn computes_rfc_accept_key() {
+        assert_eq!("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=", accept_key("dGhlIHNhbXBsZSBub25jZQ=="));
+    }
+
+    #[test]
+    fn reassembles_masked_fragments_around_a_ping() {
+        let bytes = sent_by(Role::Client, |ws| {
+            ws.write_frame(false, OP_TEXT, "héllo ".as_bytes()).unwrap();
+            ws.write_frame(true, OP_PING, b"?").unwrap();
+            ws.write_frame(true, OP_CONTINUATION, b"world").unwrap();
+            ws.send_fragmented(&Message::Binary(vec![0; 300]), 128).unwrap();
+        });
+        //every client frame has the mask bit
+        assert_eq!(0x80, bytes[1] & 0x80);
+
+        let mut server = receiver(Role::Server, bytes);
+        assert_eq!(Message::Ping(b"?".to_vec()), server.read().unwrap());
+        assert_eq!(Message::Text(String::from("héllo world")), server.read().unwrap());
+        assert_eq!(Message::Binary(vec![0; 300]), server.read().unwrap());
+        //the ping was answered with an unmasked pong
+        assert_eq!(vec![0x80 | OP_PONG, 1, b'?'], server.stream.output);
+    }
+
+    #[test]
+    fn uses_extended_lengths() {
+        let big = vec![7u8; 70_000];
+        let bytes = sent_by(Role::Server, |ws| ws.send(&Message::Binary(big.clone())).unwrap());
+        assert_eq!(127, bytes[1]);
+        assert_eq!(Message::Binary(big), receiver(Role::Client, bytes).read().unwrap());
+    }
+
+    #[test]
+    fn rejects_unmasked_client_frames_and_bad_utf8() {
+        let unmasked = sent_by(Role::Server, |ws| ws.send(&Message::Text(String::from("hi"))).unwrap());
+        let mut server = receiver(Role::Server, unmasked);
+        assert!(server.read().is_err());
+        //and told the client why, with a 1002 protocol error close frame
+        assert_eq!(0x80 | OP_CLOSE, server.stream.output[0]);
+        assert_eq!(&[0x03, 0xEA][..], &server.stream.output[2..4]);
+
+        let invalid = sent_by(Role::Client, |ws| ws.write_frame(true, OP_TEXT, &[0xFF, 0xFE]).unwrap());
+        assert!(receiver(Role::Server, invalid).read().is_err());
+    }
+
+    #[test]
+    fn echoes_close_frames() {
+        let bytes = sent_by(Role::Client, |ws| ws.close(1001, "going away").unwrap());
+        let mut server = receiver(Role::Server, bytes);
+        assert_eq!(Message::Close(Some((1001, String::from("going away")))), server.read().unwrap());
+        assert_eq!(vec![0x80 | OP_CLOSE, 2, 0x03, 0xE9], server.stream.output);
+    }
+
+    #[test]
+    fn rejects_bad_handshakes() {
+        let mut request = Request::new("GET", "/ws");
+        assert_eq!(400, upgrade(&request, |_| {}).unwrap_err().status());
+
+        request.headers.append("Upgrade", "websocket");
+        request.headers.append("Connection", "keep-alive, Upgrade");
+        request.headers.append("Sec-WebSocket-Version", "8");
+        assert_eq!(426, upgrade(&request, |_| {}).unwrap().status);
+
+        request.headers.set("Sec-WebSocket-Version", "13");
+        request.headers.set("Sec-WebSocket-Key", "too short");
+        assert_eq!(400, upgrade(&request, |_| {}).unwrap_err().status());
+    }
+
+    #[test]
+    fn echo_endpoint_over_tcp() {
+        let router = Router::new().get(
+            "/echo",
+            WebSocketHandler::new(|mut ws| {
+                while let Ok(message) = ws.read() {
+                    match message {
+                        Message::Text(_) | Message::Binary(_) => ws.send(&message).unwrap(),
+                        Message::Close(_) => break,
+                        _ => {}
+                    }
+                }
+            }),
+        );
+        let config = Config { address: String::from("127.0.0.1:0"), ..Config::default() };
+        let server = Server::bind(&config, router).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(
+                b"GET /echo HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            )
+            .unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            reader.read_line(&mut head).unwrap();
+        }
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert!(!head.contains("Content-Length"));
+
+        let mut client = WebSocket::new(TcpDuplex { reader, writer: stream }, Role::Client);
+        client.send_fragmented(&Message::Text(String::from("hello there")), 4).unwrap();
+        assert_eq!(Message::Text(String::from("hello there")), client.read().unwrap());
+        client.close(1000, "").unwrap();
+        assert_eq!(Message::Close(Some((1000, String::new()))), client.read().unwrap());
+    }
+
+    struct TcpDuplex {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+    }
+
+    impl Read for TcpDuplex {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reader.read(buf)
+        }
+    }
+
+    impl Write for TcpDuplex {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writer.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            self.writer.flush()
+        }
+    }
+}